@@ -0,0 +1,79 @@
+use crate::{
+    errors::{CompileError::*, CompileFinalResult},
+    score::Modifier,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arguments {
+    None,
+    OptionalPitch,
+    Pitches,
+}
+
+// Every modifier that may follow `with`, and the arguments it takes.
+const REGISTRY: &[(&str, Arguments)] = &[
+    ("dot", Arguments::None),
+    ("doubledot", Arguments::None),
+    ("tie", Arguments::None),
+    ("slur", Arguments::None),
+    ("endslur", Arguments::None),
+    ("fermata", Arguments::None),
+    ("staccato", Arguments::None),
+    ("staccatissimo", Arguments::None),
+    ("tenuto", Arguments::None),
+    ("accent", Arguments::None),
+    ("marcato", Arguments::None),
+    ("tremolo", Arguments::None),
+    ("trill", Arguments::OptionalPitch),
+    ("mordent", Arguments::OptionalPitch),
+    ("grace", Arguments::Pitches),
+    ("ppp", Arguments::None),
+    ("pp", Arguments::None),
+    ("p", Arguments::None),
+    ("mp", Arguments::None),
+    ("mf", Arguments::None),
+    ("f", Arguments::None),
+    ("ff", Arguments::None),
+    ("fff", Arguments::None),
+    ("sfz", Arguments::None),
+    ("fp", Arguments::None),
+    ("cresc", Arguments::None),
+    ("dim", Arguments::None),
+    ("decresc", Arguments::None),
+];
+
+pub fn check(modifier: &Modifier) -> CompileFinalResult<()> {
+    let Some((_, arguments)) = REGISTRY.iter().find(|(name, _)| *name == modifier.name) else {
+        return Err((UnknownModifier(modifier.name.to_string(), suggest(modifier.name)), modifier.location));
+    };
+    match (arguments, modifier.arguments.as_slice()) {
+        (Arguments::None, [extra, ..]) | (Arguments::OptionalPitch, [_, extra, ..]) => Err((UnexpectedArgument(extra.to_string()), extra.location())),
+        (Arguments::Pitches, []) => Err((ExpectedPitch, modifier.location)),
+        _ => Ok(()),
+    }
+}
+
+// The closest known name, if it is near enough to be a likely typo.
+fn suggest(name: &str) -> Option<&'static str> {
+    REGISTRY
+        .iter()
+        .map(|(known, _)| (distance(name, known), *known))
+        .filter(|(distance, known)| *distance <= 2 && *distance < known.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substituted = previous + usize::from(x != *y);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
@@ -0,0 +1,121 @@
+use crate::{
+    errors::{CompileError::*, CompileFinalError},
+    score::*,
+};
+
+pub fn check_measures(score: &Score) -> Vec<CompileFinalError> {
+    let mut errors = vec![];
+    for staff in &score.staves {
+        let pickup = staff.pickup.as_ref().map(Measure::length);
+        if let (Some(pickup), Some(measure)) = (pickup, &staff.pickup) {
+            if pickup >= measure.signature.length() {
+                errors.push((PickupTooLong(pickup, measure.signature), measure.location));
+            }
+        }
+        // Every voice is checked against the bar on its own.
+        for (index, measure) in staff.measures.iter().enumerate() {
+            let bar = measure.signature.length();
+            // A closing measure may complete the bar started by the pickup.
+            let last = index + 1 == staff.measures.len();
+            for voice in &measure.voices {
+                let found = voice.length();
+                if found == bar || (last && pickup.is_some_and(|pickup| pickup < bar && found + pickup == bar)) {
+                    continue;
+                }
+                if found < bar {
+                    errors.push((UnderfullMeasure(found, measure.signature), voice.location));
+                } else {
+                    errors.push((OverfullMeasure(found, measure.signature), voice.location));
+                }
+            }
+        }
+    }
+    errors
+}
+
+pub fn check_channels(score: &Score) -> Vec<CompileFinalError> {
+    let channels = score.channels();
+    score.staves.iter().zip(channels).filter(|(_, channel)| channel.is_none()).map(|(staff, _)| (TooManyChannels, staff.location)).collect()
+}
+
+// Notes outside an instrument's range still play, so these are reported as warnings.
+pub fn check_ranges(score: &Score) -> Vec<CompileFinalError> {
+    let mut warnings = vec![];
+    for staff in &score.staves {
+        let Some(instrument) = &staff.instrument else { continue };
+        for event in staff.pickup.iter().chain(&staff.measures).flat_map(Measure::events) {
+            for pitch in event.pitches.iter().filter(|pitch| !instrument.in_range(**pitch)) {
+                warnings.push((OutOfRange(*pitch, instrument.name.to_string()), event.location));
+            }
+        }
+    }
+    warnings
+}
+
+// Written accidentals that name an in-key pitch with another spelling, such as D# in Eb major.
+// Chord symbols are spelled from their intervals, so only written notes are checked.
+pub fn check_spellings(score: &Score) -> Vec<CompileFinalError> {
+    let mut warnings = vec![];
+    for staff in &score.staves {
+        for measure in staff.pickup.iter().chain(&staff.measures) {
+            for event in measure.events().filter(|event| !event.symbol) {
+                for pitch in &event.pitches {
+                    if let Some(spelled) = measure.key.scale_note(pitch.note.semitone()).filter(|spelled| *spelled != pitch.note) {
+                        warnings.push((EnharmonicClash(*pitch, spelled, measure.key.to_string()), event.location));
+                    }
+                }
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lower::compile;
+
+    fn errors(signature: &str, body: &str) -> Vec<String> {
+        let source = format!("staff s is treble() in {} {{ {} }}", signature, body);
+        let score = compile(&source).unwrap();
+        check_measures(&score).into_iter().map(|(error, _)| error.to_string()).collect()
+    }
+
+    #[test]
+    fn full_bars_pass() {
+        assert!(errors("[3/4]", "measure { 4(D) 4(C) 4(F) }").is_empty());
+        assert!(errors("[3/4]", "measure { 2(D) with dot }").is_empty());
+        assert!(errors("[4/4]", "measure { 4(C) with dot 8(D) 4(rest) 8(E) with doubledot 32(F) }").is_empty());
+        assert!(errors("[6/8]", "measure { triplet { 8(C) 8(D) 8(E) } 8(F) 4(rest) with dot }").is_empty());
+    }
+
+    #[test]
+    fn under_and_over_full_bars() {
+        assert_eq!(errors("[3/4]", "measure { 4(D) 4(C) }"), ["measure is under-full: it lasts 2/4 but the bar is [3/4]"]);
+        assert_eq!(errors("[3/4]", "measure { 2(D) 4(C) 8(rest) }"), ["measure is over-full: it lasts 7/8 but the bar is [3/4]"]);
+        assert_eq!(errors("[6/8]", "measure { 4(C) with dot 4(D) }"), ["measure is under-full: it lasts 5/8 but the bar is [6/8]"]);
+        // Each voice is counted on its own.
+        assert_eq!(
+            errors("[2/4]", "measure { voice 1 { 2(C5) } voice 2 { 4(C4) 4(D4) 4(E4) } }"),
+            ["measure is over-full: it lasts 3/4 but the bar is [2/4]"]
+        );
+    }
+
+    #[test]
+    fn pickups() {
+        assert!(errors("[3/4]", "pickup { 4(G) } measure { 2(C) with dot } measure { 2(C) }").is_empty());
+        assert_eq!(
+            errors("[3/4]", "pickup { 2(G) with dot } measure { 2(C) with dot }"),
+            ["pickup lasts 3/4 but must be shorter than a bar of [3/4]"]
+        );
+        // Only the last measure may make up for the pickup.
+        assert_eq!(
+            errors("[3/4]", "pickup { 4(G) } measure { 2(C) } measure { 2(C) with dot }"),
+            ["measure is under-full: it lasts 2/4 but the bar is [3/4]"]
+        );
+        assert_eq!(
+            errors("[3/4]", "pickup { 4(G) } measure { 2(C) with dot } measure { 4(C) }"),
+            ["measure is under-full: it lasts 1/4 but the bar is [3/4]"]
+        );
+    }
+}
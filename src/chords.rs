@@ -0,0 +1,245 @@
+use crate::{score::Pitch, tokens::Note};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoicingStyle {
+    Close,
+    Drop2,
+    Root,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Voicing {
+    pub style: VoicingStyle,
+    pub octave: u8,
+}
+
+#[derive(Debug)]
+pub struct ChordSymbol {
+    pub root: Note,
+    pub intervals: Vec<i8>,
+    pub bass: Option<Note>,
+}
+
+impl Default for Voicing {
+    fn default() -> Self {
+        Self { style: VoicingStyle::Close, octave: Note::DEFAULT_OCTAVE }
+    }
+}
+
+impl ChordSymbol {
+    // Parses symbols such as `Cmaj7`, `F#m7b5`, `G13sus4`, `C6/9` and `C/E`.
+    pub fn parse(symbol: &str) -> Option<ChordSymbol> {
+        let symbol = symbol.replacen("6/9", "69", 1);
+        let (symbol, bass) = match symbol.split_once('/') {
+            Some((symbol, bass)) => match root(bass)? {
+                (bass, "") => (symbol, Some(bass)),
+                _ => return None,
+            },
+            None => (symbol.as_str(), None),
+        };
+        let (root, mut rest) = root(symbol)?;
+        let mut third = Some(4);
+        let mut fifth = Some(7);
+        let mut seventh = None;
+        let mut major = false;
+        let mut diminished = false;
+        if eat(&mut rest, &["ø"]) {
+            (third, fifth, seventh) = (Some(3), Some(6), Some(10));
+        } else if eat(&mut rest, &["dim", "°"]) {
+            (third, fifth, diminished) = (Some(3), Some(6), true);
+        } else if eat(&mut rest, &["aug", "+"]) {
+            fifth = Some(8);
+        } else if eat(&mut rest, &["maj", "Maj", "M", "Δ"]) {
+            major = true;
+        } else if eat(&mut rest, &["min", "m", "-"]) {
+            third = Some(3);
+            major = eat(&mut rest, &["maj", "Maj", "M", "Δ"]);
+        }
+        let seventh_interval = if major { 11 } else if diminished { 9 } else { 10 };
+        let mut extensions = vec![];
+        for (number, added) in [("69", &[9, 14][..]), ("13", &[14, 21]), ("11", &[14, 17]), ("9", &[14]), ("7", &[]), ("6", &[9]), ("5", &[])] {
+            if !eat(&mut rest, &[number]) {
+                continue;
+            }
+            match number {
+                "5" => third = None,
+                "6" | "69" => {}
+                _ => seventh = seventh.or(Some(seventh_interval)),
+            }
+            extensions.extend_from_slice(added);
+            break;
+        }
+        while !rest.is_empty() {
+            if eat(&mut rest, &["sus2"]) {
+                third = Some(2);
+            } else if eat(&mut rest, &["sus4", "sus"]) {
+                third = Some(5);
+            } else if eat(&mut rest, &["add9", "add2"]) {
+                extensions.push(14);
+            } else if eat(&mut rest, &["add11", "add4"]) {
+                extensions.push(17);
+            } else if eat(&mut rest, &["add13"]) {
+                extensions.push(21);
+            } else if eat(&mut rest, &["no3"]) {
+                third = None;
+            } else if eat(&mut rest, &["no5"]) {
+                fifth = None;
+            } else if eat(&mut rest, &["b5"]) {
+                fifth = Some(6);
+            } else if eat(&mut rest, &["#5"]) {
+                fifth = Some(8);
+            } else {
+                // Altered extensions replace their natural counterpart.
+                let (natural, altered) = [("b9", 14, 13), ("#9", 14, 15), ("#11", 17, 18), ("b13", 21, 20)]
+                    .into_iter()
+                    .find(|(name, _, _)| eat(&mut rest, &[name]))
+                    .map(|(_, natural, altered)| (natural, altered))?;
+                extensions.retain(|interval| *interval != natural);
+                extensions.push(altered);
+            }
+        }
+        let mut intervals: Vec<i8> = [Some(0), third, fifth, seventh].into_iter().flatten().chain(extensions).collect();
+        intervals.sort();
+        intervals.dedup();
+        Some(ChordSymbol { root, intervals, bass })
+    }
+    // Each tone is spelled on the letter of its degree, so Dmaj7 has F# and C# and Cdim7 has Bbb.
+    pub fn voice(&self, voicing: Voicing) -> Vec<Pitch> {
+        let root = (voicing.octave as i32 + 1) * 12 + self.root.semitone() as i32;
+        let letter = |degree: usize| LETTERS[(LETTERS.iter().position(|letter| *letter == self.root.letter()).unwrap_or(0) + degree) % 7];
+        let mut keys: Vec<(i32, char)> = self
+            .intervals
+            .iter()
+            .map(|interval| {
+                let key = match voicing.style {
+                    VoicingStyle::Root => root + *interval as i32,
+                    VoicingStyle::Close | VoicingStyle::Drop2 => root + (*interval % 12) as i32,
+                };
+                (key, letter(self.degree(*interval)))
+            })
+            .collect();
+        keys.sort();
+        keys.dedup_by_key(|(key, _)| *key);
+        if voicing.style == VoicingStyle::Drop2 && keys.len() >= 3 {
+            let second = keys.len() - 2;
+            keys[second].0 -= 12;
+            keys.sort();
+        }
+        // A slash bass sits below the rest of the voicing.
+        if let (Some(bass), Some(&(lowest, _))) = (self.bass, keys.first()) {
+            let mut key = lowest.div_euclid(12) * 12 + bass.semitone() as i32;
+            while key >= lowest {
+                key -= 12;
+            }
+            keys.insert(0, (key, bass.letter()));
+        }
+        keys.into_iter().filter(|(key, _)| (12..=127).contains(key)).map(|(key, letter)| spell(key, letter)).collect()
+    }
+    // Steps above the root in letter names. A diminished chord's 9 is a double-flat seventh
+    // rather than a sixth.
+    fn degree(&self, interval: i8) -> usize {
+        let diminished = self.intervals.contains(&3) && self.intervals.contains(&6) && !self.intervals.iter().any(|interval| matches!(interval, 7 | 10 | 11));
+        match interval % 12 {
+            0 => 0,
+            1 | 2 => 1,
+            3 if interval > 12 => 1,
+            3 | 4 => 2,
+            5 => 3,
+            6 if interval > 12 => 3,
+            8 if interval > 12 => 5,
+            6..=8 => 4,
+            9 if diminished => 6,
+            9 => 5,
+            _ => 6,
+        }
+    }
+}
+
+const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+// Names a MIDI key on the given letter, falling back to a plain spelling past double accidentals.
+fn spell(key: i32, letter: char) -> Pitch {
+    let natural = Note::spell(letter, 0).map_or(0, |note| note.semitone()) as i32;
+    let accidental = (key - natural + 6).rem_euclid(12) - 6;
+    let note = Note::spell(letter, accidental as i8).unwrap_or_else(|| Note::from_semitone(key.rem_euclid(12) as i8, accidental < 0));
+    Pitch { note, octave: ((key - note.semitone() as i32) / 12 - 1).max(0) as u8 }
+}
+
+fn root(symbol: &str) -> Option<(Note, &str)> {
+    let mut chars = symbol.chars();
+    let letter = chars.next().filter(|letter| matches!(letter, 'A'..='G'))?;
+    let mut rest = chars.as_str();
+    let accidental = if eat(&mut rest, &["#"]) {
+        1
+    } else if eat(&mut rest, &["b"]) {
+        -1
+    } else {
+        0
+    };
+    let note = Note::spell(letter, accidental)?;
+    Some((note, rest))
+}
+
+fn eat(rest: &mut &str, prefixes: &[&str]) -> bool {
+    match prefixes.iter().find_map(|prefix| rest.strip_prefix(prefix)) {
+        Some(remaining) => {
+            *rest = remaining;
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intervals(symbol: &str) -> Vec<i8> {
+        ChordSymbol::parse(symbol).map(|chord| chord.intervals).unwrap_or_default()
+    }
+
+    #[test]
+    fn sevenths_and_extensions() {
+        assert_eq!(intervals("G7"), [0, 4, 7, 10]);
+        assert_eq!(intervals("Cmaj7"), [0, 4, 7, 11]);
+        assert_eq!(intervals("C9"), [0, 4, 7, 10, 14]);
+        assert_eq!(intervals("F#m7b5"), [0, 3, 6, 10]);
+        assert_eq!(intervals("G7b9"), [0, 4, 7, 10, 13]);
+    }
+
+    #[test]
+    fn six_nine_is_not_a_slash_chord() {
+        let chord = ChordSymbol::parse("C6/9").unwrap();
+        assert_eq!(chord.intervals, [0, 4, 7, 9, 14]);
+        assert_eq!(chord.bass, None);
+        assert_eq!(ChordSymbol::parse("C6/9/E").unwrap().bass, Some(Note::E));
+    }
+
+    #[test]
+    fn slash_bass() {
+        let chord = ChordSymbol::parse("C/E").unwrap();
+        assert_eq!((chord.root, chord.bass), (Note::C, Some(Note::E)));
+        assert!(ChordSymbol::parse("C/H").is_none());
+    }
+
+    fn notes(symbol: &str) -> Vec<String> {
+        let chord = ChordSymbol::parse(symbol).unwrap();
+        chord.voice(Voicing::default()).iter().map(Pitch::to_string).collect()
+    }
+
+    #[test]
+    fn spells_tones_by_degree() {
+        assert_eq!(notes("Dmaj7"), ["D4", "F#4", "A4", "C#5"]);
+        assert_eq!(notes("Cdim7"), ["C4", "Eb4", "Gb4", "Bbb4"]);
+        assert_eq!(notes("Ebm7"), ["Eb4", "Gb4", "Bb4", "Db5"]);
+        assert_eq!(notes("C7#9"), ["C4", "D#4", "E4", "G4", "Bb4"]);
+        assert_eq!(notes("Dbmaj7/F"), ["F3", "Db4", "F4", "Ab4", "C5"]);
+    }
+
+    #[test]
+    fn rejects_pitches_and_words() {
+        assert!(ChordSymbol::parse("C4").is_none());
+        assert!(ChordSymbol::parse("rest").is_none());
+        assert!(ChordSymbol::parse("Cfoo").is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use crate::{
+    check,
+    diagnostics::Diagnostic,
+    dynamics,
+    fmt,
+    lexer::{self, Lexer},
+    lower,
+    midi,
+    modules::{self, ModuleLoader, SourceFile},
+    parser::Parser,
+    score::Score,
+    tempo,
+};
+
+const USAGE: &str = "\
+usage: tonal <command> [file] [-I <dir>]...
+
+commands:
+    check <file>              parse and validate a score
+    build <file> [-o <out>]   compile a score to a MIDI file
+    dump-tokens <file>        print the token stream
+    dump-ast <file>           print the syntax tree
+    fmt <file> [--check]      rewrite a score in canonical form, or with
+                              --check, fail if it is not already
+
+<file> may be `-` or omitted to read from stdin.
+-I <dir> adds a directory to search for imported modules.";
+
+#[derive(Debug)]
+pub enum Command {
+    Check,
+    Build { output: Option<PathBuf> },
+    DumpTokens,
+    DumpAst,
+    Fmt { check: bool },
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub input: Option<PathBuf>,
+    pub include: Vec<PathBuf>,
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut command = match args.next().as_deref() {
+        Some("check") => Command::Check,
+        Some("build") => Command::Build { output: None },
+        Some("dump-tokens") => Command::DumpTokens,
+        Some("dump-ast") => Command::DumpAst,
+        Some("fmt") => Command::Fmt { check: false },
+        Some(other) => return Err(format!("unknown command `{}`", other)),
+        None => return Err("missing command".to_string()),
+    };
+    let mut input = None;
+    let mut include = vec![];
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), &mut command) {
+            ("-I" | "--include", _) => match args.next() {
+                Some(path) => include.push(path.into()),
+                None => return Err(format!("`{}` needs a path", arg)),
+            },
+            ("-o" | "--output", Command::Build { output }) => match args.next() {
+                Some(path) => *output = Some(path.into()),
+                None => return Err(format!("`{}` needs a path", arg)),
+            },
+            ("--check", Command::Fmt { check }) => *check = true,
+            ("-", _) if input.is_none() => input = Some(arg),
+            (flag, _) if flag.starts_with('-') => return Err(format!("unknown option `{}`", flag)),
+            (_, _) if input.is_none() => input = Some(arg),
+            (_, _) => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+    let input = input.filter(|path| path != "-").map(PathBuf::from);
+    Ok(Options { command, input, include })
+}
+
+pub fn run(args: impl Iterator<Item = String>) -> ExitCode {
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let file = match read_input(options.input.as_deref()) {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = |files: &[SourceFile], diagnostics: &[Diagnostic]| {
+        for diagnostic in diagnostics {
+            eprint!("{}", diagnostic.render(files));
+        }
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        }
+    };
+    match options.command {
+        Command::DumpTokens => {
+            let tokens = match lexer::tokenize(&file.source) {
+                Ok(tokens) => tokens,
+                Err(err) => return report(std::slice::from_ref(&file), &[Diagnostic::from(err)]),
+            };
+            let mut out = std::io::stdout().lock();
+            for token in tokens {
+                let loc = token.location();
+                let _ = writeln!(out, "{}:{}\t{}", loc.line, loc.col, token.describe());
+            }
+            ExitCode::SUCCESS
+        }
+        Command::DumpAst => {
+            let parser = Parser::new(Lexer::new(&file.source));
+            let (node, errors) = parser.parse();
+            let _ = writeln!(std::io::stdout().lock(), "{:#?}", node);
+            let diagnostics: Vec<_> = errors.into_iter().map(Diagnostic::from).collect();
+            report(std::slice::from_ref(&file), &diagnostics)
+        }
+        Command::Fmt { check } => {
+            let formatted = match fmt::format(&file.source) {
+                Ok(formatted) => formatted,
+                Err(errors) => {
+                    let diagnostics: Vec<_> = errors.into_iter().map(Diagnostic::from).collect();
+                    return report(std::slice::from_ref(&file), &diagnostics);
+                }
+            };
+            if check {
+                if formatted == file.source {
+                    return ExitCode::SUCCESS;
+                }
+                eprintln!("{} is not formatted", file.name);
+                return ExitCode::FAILURE;
+            }
+            match &file.path {
+                Some(path) if formatted != file.source => {
+                    if let Err(err) = std::fs::write(path, formatted) {
+                        eprintln!("error: could not write {}: {}", path.display(), err);
+                        return ExitCode::FAILURE;
+                    }
+                }
+                Some(_) => {}
+                None => print!("{}", formatted),
+            }
+            ExitCode::SUCCESS
+        }
+        Command::Check => {
+            let mut loader = ModuleLoader::new(options.include);
+            let diagnostics = loader.load(file);
+            if !diagnostics.is_empty() {
+                return report(loader.files(), &diagnostics);
+            }
+            let (_, diagnostics) = compile(&loader);
+            report(loader.files(), &diagnostics)
+        }
+        Command::Build { output } => {
+            let mut loader = ModuleLoader::new(options.include);
+            let diagnostics = loader.load(file);
+            if !diagnostics.is_empty() {
+                return report(loader.files(), &diagnostics);
+            }
+            let (score, diagnostics) = compile(&loader);
+            let status = report(loader.files(), &diagnostics);
+            let score = match score {
+                Some(score) if status == ExitCode::SUCCESS => score,
+                _ => return ExitCode::FAILURE,
+            };
+            let output = output.unwrap_or_else(|| match &options.input {
+                Some(input) => input.with_extension("mid"),
+                None => PathBuf::from("out.mid"),
+            });
+            if let Err(err) = std::fs::write(&output, midi::export(&score)) {
+                eprintln!("error: could not write {}: {}", output.display(), err);
+                return ExitCode::FAILURE;
+            }
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn read_input(path: Option<&Path>) -> Result<SourceFile, String> {
+    match path {
+        Some(path) => std::fs::read_to_string(path)
+            .map(|source| SourceFile::new(path.display().to_string(), Some(path.to_path_buf()), source))
+            .map_err(|err| format!("could not read {}: {}", path.display(), err)),
+        None => {
+            let mut source = String::new();
+            std::io::stdin()
+                .read_to_string(&mut source)
+                .map(|_| SourceFile::new("<stdin>", None, source))
+                .map_err(|err| format!("could not read stdin: {}", err))
+        }
+    }
+}
+
+// The loader has already reported parse errors, so only lowering and checking remain.
+// Warnings are returned alongside the score; errors leave no score behind.
+fn compile(loader: &ModuleLoader) -> (Option<Score<'_>>, Vec<Diagnostic>) {
+    let modules = loader.parse();
+    let scope = match modules::scope(&modules, 0) {
+        Ok(scope) => scope,
+        Err(diagnostics) => return (None, diagnostics),
+    };
+    let mut score = match lower::lower(&modules[0].program, &scope) {
+        Ok(score) => score,
+        Err(err) => return (None, vec![err.into()]),
+    };
+    score.tempo = match tempo::build(&score) {
+        Ok(tempo) => tempo,
+        Err(errors) => return (None, errors.into_iter().map(Diagnostic::from).collect()),
+    };
+    if let Err(errors) = dynamics::apply(&mut score) {
+        return (None, errors.into_iter().map(Diagnostic::from).collect());
+    }
+    let errors = check::check_measures(&score).into_iter().chain(check::check_channels(&score));
+    let mut diagnostics: Vec<_> = errors.map(Diagnostic::from).collect();
+    let errors = !diagnostics.is_empty();
+    let warnings = check::check_ranges(&score).into_iter().chain(check::check_spellings(&score));
+    diagnostics.extend(warnings.map(|(err, location)| Diagnostic::warning(err.to_string(), location)));
+    (Some(score).filter(|_| !errors), diagnostics)
+}
@@ -0,0 +1,121 @@
+use std::fmt::Write;
+
+use crate::{
+    errors::{CompileFinalError, LexFinalError, ModuleFinalError, ParseError, ParseFinalError},
+    modules::SourceFile,
+    tokens::Location,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub message: String,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Location,
+    pub label: Option<String>,
+    pub notes: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, location: Location) -> Self {
+        Self { severity: Severity::Error, message: message.into(), location, label: None, notes: vec![] }
+    }
+    pub fn warning(message: impl Into<String>, location: Location) -> Self {
+        Self { severity: Severity::Warning, ..Self::error(message, location) }
+    }
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+    pub fn with_note(mut self, message: impl Into<String>, location: Location) -> Self {
+        self.notes.push(Label { message: message.into(), location });
+        self
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+    pub fn render(&self, files: &[SourceFile]) -> String {
+        let mut out = String::new();
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let _ = writeln!(out, "{}: {}", severity, self.message);
+        snippet(&mut out, files, self.location, self.label.as_deref());
+        for note in &self.notes {
+            let _ = writeln!(out, "note: {}", note.message);
+            snippet(&mut out, files, note.location, None);
+        }
+        out
+    }
+}
+
+fn snippet(out: &mut String, files: &[SourceFile], location: Location, label: Option<&str>) {
+    let (name, source) = match files.get(location.file) {
+        Some(file) => (file.name.as_str(), file.source.as_str()),
+        None => return,
+    };
+    let start = location.span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |pos| pos + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |pos| start + pos);
+    let line = source[..start].matches('\n').count() + 1;
+    let col = source[line_start..start].chars().count() + 1;
+    let text = source[line_start..line_end].trim_end_matches('\r');
+    let end = location.span.end.clamp(start, line_end);
+    let width = source[start..end].chars().count().max(1);
+    let gutter = " ".repeat(line.to_string().len());
+
+    let _ = writeln!(out, "{}--> {}:{}:{}", gutter, name, line, col);
+    let _ = writeln!(out, "{} |", gutter);
+    let _ = writeln!(out, "{} | {}", line, text);
+    let _ = write!(out, "{} | {}{}", gutter, " ".repeat(col - 1), "^".repeat(width));
+    match label {
+        Some(label) => {
+            let _ = writeln!(out, " {}", label);
+        }
+        None => out.push('\n'),
+    }
+}
+
+impl From<ParseFinalError> for Diagnostic {
+    fn from(err: ParseFinalError) -> Self {
+        let message = match err.error {
+            ParseError::EmptyMeta | ParseError::Lex(_) => err.error.to_string(),
+            _ => format!("{}, found {}", err.error, err.found),
+        };
+        let mut diagnostic = Diagnostic::error(message, err.location).with_label(err.error.to_string());
+        for (what, location) in err.context.into_iter().rev() {
+            diagnostic = diagnostic.with_note(what, location);
+        }
+        diagnostic
+    }
+}
+
+impl From<LexFinalError> for Diagnostic {
+    fn from((err, location): LexFinalError) -> Self {
+        Diagnostic::error(err.to_string(), location)
+    }
+}
+
+impl From<CompileFinalError> for Diagnostic {
+    fn from((err, location): CompileFinalError) -> Self {
+        Diagnostic::error(err.to_string(), location)
+    }
+}
+
+impl From<ModuleFinalError> for Diagnostic {
+    fn from((err, location): ModuleFinalError) -> Self {
+        Diagnostic::error(err.to_string(), location)
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    errors::{CompileError::*, CompileFinalError},
+    score::*,
+    tokens::Location,
+};
+
+struct Open {
+    index: usize,
+    position: Fraction,
+    from: Dynamic,
+    hairpin: Hairpin,
+    location: Location,
+}
+
+// Gives every note a velocity from the dynamic in force, with hairpins ramping linearly in time
+// from where they start to the next dynamic.
+pub fn apply(score: &mut Score) -> Result<(), Vec<CompileFinalError>> {
+    let table = score.velocities;
+    let mut errors = vec![];
+    for staff in &mut score.staves {
+        for number in staff.voices() {
+            let positions = staff.voice_positions(number);
+            apply_voice(staff.voice_events_mut(number), &positions, table, &mut errors);
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(())
+}
+
+// Dynamics carry through a voice, so each voice of a staff keeps its own level and hairpins.
+fn apply_voice(mut events: Vec<&mut Event>, positions: &[Fraction], table: VelocityTable, errors: &mut Vec<CompileFinalError>) {
+    let mut level = Dynamic::Mf;
+    let mut open: Option<Open> = None;
+    for index in 0..events.len() {
+        // An `sfz` inside a hairpin accents its note without ending the hairpin.
+        let end = events[index].dynamic.filter(|dynamic| *dynamic != Dynamic::Sfz);
+        if let Some((dynamic, hairpin)) = end.and_then(|dynamic| Some((dynamic, open.take()?))) {
+            let (name, louder) = match hairpin.hairpin {
+                Hairpin::Crescendo => ("cresc", true),
+                Hairpin::Diminuendo => ("dim", false),
+            };
+            let (from, to) = (table.get(hairpin.from), table.get(dynamic));
+            if (louder && to <= from) || (!louder && to >= from) {
+                errors.push((BadHairpin(name, hairpin.from, dynamic), hairpin.location));
+                continue;
+            }
+            let span = positions[index] - hairpin.position;
+            for between in hairpin.index + 1..index {
+                if events[between].dynamic == Some(Dynamic::Sfz) {
+                    continue;
+                }
+                let step = (to as f64 - from as f64) * (positions[between] - hairpin.position).ratio(span);
+                events[between].velocity = (from as f64 + step).round() as u8;
+            }
+        }
+        let event = &mut events[index];
+        event.velocity = table.get(event.dynamic.unwrap_or(level));
+        if let Some(dynamic) = event.dynamic {
+            level = dynamic.after(level);
+        }
+        if let Some(hairpin) = event.hairpin {
+            if open.is_some() {
+                errors.push((NestedHairpin, event.location));
+                continue;
+            }
+            open = Some(Open { index, position: positions[index], from: level, hairpin, location: event.location });
+        }
+    }
+    if let Some(hairpin) = open {
+        errors.push((UnresolvedHairpin, hairpin.location));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lower::compile;
+
+    fn velocities(measures: &str) -> Result<Vec<u8>, Vec<CompileFinalError>> {
+        let source = format!("staff s is treble() in [4/4] {{ {} }}", measures);
+        let mut score = compile(&source).unwrap();
+        apply(&mut score)?;
+        Ok(score.staves[0].measures.iter().flat_map(Measure::events).map(|event| event.velocity).collect())
+    }
+
+    #[test]
+    fn hairpins_ramp_in_time() {
+        let cases = [
+            ("measure { 4(C) 4(D) with p 4(E) 4(F) with ff }", vec![80, 49, 49, 112]),
+            ("measure { 4(C) with p with cresc 4(D) 4(E) 4(F) } measure { 1(G) with f }", vec![49, 61, 73, 84, 96]),
+            ("measure { 2(C) with p with cresc 4(D) 4(E) } measure { 1(F) with f }", vec![49, 73, 84, 96]),
+            ("measure { 2(C) with f with dim 2(D) } measure { 1(E) with p }", vec![96, 73, 49]),
+            // A sudden accent keeps its own velocity and does not move the hairpin.
+            ("measure { 4(C) with p with cresc 4(D) with sfz 4(E) 4(F) } measure { 1(G) with f }", vec![49, 120, 73, 84, 96]),
+            ("measure { 4(C) with fp 4(D) 2(E) with sfz } measure { 1(F) }", vec![96, 49, 120, 49]),
+        ];
+        for (measures, expected) in cases {
+            assert_eq!(velocities(measures).unwrap(), expected, "{}", measures);
+        }
+    }
+
+    #[test]
+    fn hairpin_errors() {
+        let errors = velocities("measure { 4(C) with f with cresc 4(D) 2(E) with p }").unwrap_err();
+        assert!(matches!(errors.as_slice(), [(BadHairpin("cresc", Dynamic::F, Dynamic::P), _)]));
+        let errors = velocities("measure { 4(C) with p with dim 4(D) 2(E) with f }").unwrap_err();
+        assert!(matches!(errors.as_slice(), [(BadHairpin("dim", Dynamic::P, Dynamic::F), _)]));
+        let errors = velocities("measure { 4(C) with p with cresc 4(D) 2(E) }").unwrap_err();
+        assert!(matches!(errors.as_slice(), [(UnresolvedHairpin, Location { col: 42, .. })]));
+        let errors = velocities("measure { 4(C) with p with cresc 4(D) with dim 2(E) with f }").unwrap_err();
+        assert!(matches!(errors.as_slice(), [(NestedHairpin, _)]));
+    }
+}
@@ -1,4 +1,28 @@
-use crate::tokens::{TokenType, Keyword, Separator, Location};
+use crate::{tokens::{TokenType, Keyword, Separator, Location, Note}, score::{Dynamic, Fraction, Pitch, TimeSignature}};
+pub type LexResult<T> = Result<T, LexFinalError>;
+#[derive(Debug, Clone)]
+pub enum LexError {
+    UnterminatedLiteral,
+    UnterminatedComment,
+    BadSignature,
+    NumberOverflow(String),
+    InvalidCharacter(char),
+}
+
+pub type LexFinalError = (LexError, Location);
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexError::UnterminatedLiteral => write!(f, "unterminated string literal"),
+            LexError::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexError::BadSignature => write!(f, "malformed time signature, expected something like [3/4]"),
+            LexError::NumberOverflow(number) => write!(f, "number {} is too large", number),
+            LexError::InvalidCharacter(c) => write!(f, "invalid character `{}`", c),
+        }
+    }
+}
+
 pub type ParseResult<T> = Result<T, ParseError>;
 #[derive(Debug)]
 pub enum ParseError {
@@ -7,9 +31,191 @@ pub enum ParseError {
     ExpectedSeparator(Separator),
     ExpectedArgument,
     EmptyMeta,
+    Lex(LexError),
     Unknown
 }
 
-pub type ParseFinalError = (ParseError, Location);
+#[derive(Debug)]
+pub struct ParseFinalError {
+    pub error: ParseError,
+    pub found: String,
+    pub location: Location,
+    pub context: Vec<(&'static str, Location)>,
+}
+
+pub type ParseOutput<T> = (T, Vec<ParseFinalError>);
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::ExpectedType(ty) => write!(f, "expected {}", ty),
+            ParseError::ExpectedKeyword(word) => write!(f, "expected keyword `{}`", format!("{:?}", word).to_lowercase()),
+            ParseError::ExpectedSeparator(sep) => write!(f, "expected `{}`", sep),
+            ParseError::ExpectedArgument => write!(f, "expected an argument"),
+            ParseError::EmptyMeta => write!(f, "meta block must not be empty"),
+            ParseError::Lex(err) => write!(f, "{}", err),
+            ParseError::Unknown => write!(f, "unexpected input"),
+        }
+    }
+}
+
+pub type CompileResult<T> = Result<T, CompileError>;
+#[derive(Debug)]
+pub enum CompileError {
+    UnknownDuration(String),
+    UnknownPitch(String),
+    InvalidNote(String),
+    UnknownTuplet(String),
+    BadTuplet(u32, u32),
+    TooManyDots,
+    ExpectedPitch,
+    DuplicatePitch(String),
+    RestInChord,
+    SymbolInChord(String),
+    UnknownChord(String),
+    UnmarkedChord(String),
+    UnknownVoicing(String),
+    UnknownKey(String),
+    UnknownMode(String),
+    EnharmonicClash(Pitch, Note, String),
+    UnexpectedArgument(String),
+    ExpectedLiteral,
+    ExpectedNumber,
+    UnknownMeta(String),
+    UnknownClef(String),
+    UnknownDirective(String),
+    DanglingDirective,
+    UnmatchedTie,
+    UnmatchedSlur,
+    UnclosedSlur,
+    NestedSlur,
+    UnknownModifier(String, Option<&'static str>),
+    ArticulationOnRest(String),
+    UnknownDynamic(String),
+    BadVelocity(u32),
+    DuplicateDynamic,
+    NestedHairpin,
+    UnresolvedHairpin,
+    BadHairpin(&'static str, Dynamic, Dynamic),
+    NestedVoice,
+    NoteOutsideVoice,
+    BadVoice(String),
+    DuplicateVoice(u32),
+    NestedRepeat,
+    BadRepeat(u32),
+    DuplicateEnding(u32),
+    EndingOutOfRange(u32, u32),
+    MarkInRepeat(String),
+    DuplicateMark(String),
+    DuplicateJump,
+    UnknownJump(String),
+    MissingMark(&'static str),
+    UnknownInstrument(String),
+    UnknownSetting(String),
+    BadProgram(u32),
+    OutOfRange(Pitch, String),
+    BadSignature(u32, u32),
+    BadTempo(u32),
+    BadRamp(&'static str, u32, u32),
+    TempoConflict(u32, u32),
+    UnderfullMeasure(Fraction, TimeSignature),
+    OverfullMeasure(Fraction, TimeSignature),
+    PickupTooLong(Fraction, TimeSignature),
+    TooManyChannels,
+}
+
+pub type CompileFinalError = (CompileError, Location);
+
+pub type CompileFinalResult<T> = Result<T, CompileFinalError>;
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::UnknownDuration(name) => write!(f, "unknown duration `{}`", name),
+            CompileError::UnknownPitch(name) => write!(f, "unknown pitch `{}`", name),
+            CompileError::InvalidNote(name) => write!(f, "invalid note `{}`, notes are named A to G", name),
+            CompileError::UnknownTuplet(name) => write!(f, "unknown tuplet `{}`", name),
+            CompileError::BadTuplet(actual, normal) => write!(f, "invalid tuplet ratio {}:{}", actual, normal),
+            CompileError::TooManyDots => write!(f, "a note value takes at most two dots"),
+            CompileError::ExpectedPitch => write!(f, "expected a pitch or `rest`"),
+            CompileError::DuplicatePitch(pitch) => write!(f, "{} appears more than once in the chord", pitch),
+            CompileError::RestInChord => write!(f, "`rest` cannot be part of a chord"),
+            CompileError::SymbolInChord(symbol) => write!(f, "chord symbol `{}` cannot be combined with other pitches", symbol),
+            CompileError::UnknownChord(symbol) => write!(f, "unknown chord symbol `{}`", symbol),
+            CompileError::UnmarkedChord(symbol) => write!(f, "chord symbols are written as `chord({})`", symbol),
+            CompileError::UnknownVoicing(name) => write!(f, "unknown voicing `{}`, expected close, drop2 or root", name),
+            CompileError::UnknownKey(key) => write!(f, "{} is not a key with a standard key signature", key),
+            CompileError::UnknownMode(mode) => write!(f, "unknown mode `{}`, expected major or minor", mode),
+            CompileError::EnharmonicClash(pitch, spelled, key) => write!(f, "{} is spelled {} in {}", pitch, spelled, key),
+            CompileError::UnexpectedArgument(arg) => write!(f, "unexpected argument `{}`", arg),
+            CompileError::ExpectedLiteral => write!(f, "expected a string literal"),
+            CompileError::ExpectedNumber => write!(f, "expected a number"),
+            CompileError::UnknownMeta(name) => write!(f, "unknown meta field `{}`", name),
+            CompileError::UnknownClef(name) => write!(f, "unknown clef `{}`", name),
+            CompileError::UnknownDirective(name) => write!(f, "unknown staff directive `{}`", name),
+            CompileError::UnknownInstrument(name) => write!(f, "instrument `{}` is not declared or imported", name),
+            CompileError::UnknownSetting(name) => write!(f, "unknown instrument setting `{}`", name),
+            CompileError::BadProgram(program) => write!(f, "invalid program number {}, expected 1 to 128", program),
+            CompileError::OutOfRange(pitch, instrument) => write!(f, "{} is outside the playable range of `{}`", pitch, instrument),
+            CompileError::UnmatchedTie => write!(f, "tie is not followed by a note of the same pitch"),
+            CompileError::UnmatchedSlur => write!(f, "`endslur` has no matching `slur`"),
+            CompileError::UnclosedSlur => write!(f, "slur is never closed with `endslur`"),
+            CompileError::NestedSlur => write!(f, "slur starts before the previous one is closed"),
+            CompileError::UnknownModifier(name, Some(suggestion)) => write!(f, "unknown modifier `{}`, did you mean `{}`?", name, suggestion),
+            CompileError::UnknownModifier(name, None) => write!(f, "unknown modifier `{}`", name),
+            CompileError::ArticulationOnRest(name) => write!(f, "`{}` cannot be applied to a rest", name),
+            CompileError::UnknownDynamic(name) => write!(f, "unknown dynamic `{}`", name),
+            CompileError::BadVelocity(velocity) => write!(f, "invalid velocity {}, expected 1 to 127", velocity),
+            CompileError::DuplicateDynamic => write!(f, "note has more than one dynamic"),
+            CompileError::NestedHairpin => write!(f, "hairpin starts before the previous one reaches a dynamic"),
+            CompileError::UnresolvedHairpin => write!(f, "hairpin is not followed by a dynamic to end on"),
+            CompileError::BadHairpin(name, from, to) => write!(f, "`{}` cannot go from {} to {}", name, from, to),
+            CompileError::NestedVoice => write!(f, "voices must be written directly inside a measure"),
+            CompileError::NoteOutsideVoice => write!(f, "measure is split into voices, so this must be inside a voice"),
+            CompileError::BadVoice(number) => write!(f, "invalid voice number {}, voices are numbered from 1", number),
+            CompileError::DuplicateVoice(number) => write!(f, "voice {} appears twice in this measure", number),
+            CompileError::NestedRepeat => write!(f, "repeats cannot be nested"),
+            CompileError::BadRepeat(times) => write!(f, "a repeat must play at least twice, found {}", times),
+            CompileError::DuplicateEnding(pass) => write!(f, "pass {} already has an ending", pass),
+            CompileError::EndingOutOfRange(pass, times) => write!(f, "ending for pass {} but the repeat only plays {} times", pass, times),
+            CompileError::MarkInRepeat(name) => write!(f, "`{}` cannot be used inside a repeat", name),
+            CompileError::DuplicateMark(name) => write!(f, "staff already has a `{}` mark", name),
+            CompileError::DuplicateJump => write!(f, "staff already has a `dc` or `ds` jump"),
+            CompileError::UnknownJump(name) => write!(f, "unknown jump ending `{}`, expected `fine` or `coda`", name),
+            CompileError::MissingMark(name) => write!(f, "jump needs a `{}()` mark on the same staff", name),
+            CompileError::DanglingDirective => write!(f, "staff directive is not followed by a measure"),
+            CompileError::BadSignature(top, bottom) => write!(f, "invalid time signature [{}/{}]", top, bottom),
+            CompileError::BadTempo(bpm) => write!(f, "invalid tempo {}", bpm),
+            CompileError::BadRamp(name, from, to) => write!(f, "`{}` cannot go from {} to {} bpm", name, from, to),
+            CompileError::TempoConflict(bpm, other) => write!(f, "tempo {} bpm conflicts with {} bpm set by another staff at the same point", bpm, other),
+            CompileError::UnderfullMeasure(found, bar) => write!(f, "measure is under-full: it lasts {} but the bar is {}", bar.written(*found), bar),
+            CompileError::OverfullMeasure(found, bar) => write!(f, "measure is over-full: it lasts {} but the bar is {}", bar.written(*found), bar),
+            CompileError::PickupTooLong(found, bar) => write!(f, "pickup lasts {} but must be shorter than a bar of {}", bar.written(*found), bar),
+            CompileError::TooManyChannels => write!(f, "no MIDI channel left for this staff: at most 15 programs can play besides percussion"),
+        }
+    }
+}
 
-pub type ParseFinalResult<T> = Result<T, ParseFinalError>;
+pub type ModuleResult<T> = Result<T, ModuleError>;
+#[derive(Debug)]
+pub enum ModuleError {
+    NotFound(String),
+    Unreadable(String, String),
+    Cyclic(Vec<String>),
+    UnknownExport(String, String),
+    Redefined(String),
+}
+
+pub type ModuleFinalError = (ModuleError, Location);
+
+impl std::fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleError::NotFound(module) => write!(f, "cannot find module \"{}\"", module),
+            ModuleError::Unreadable(module, err) => write!(f, "cannot read module \"{}\": {}", module, err),
+            ModuleError::Cyclic(chain) => write!(f, "cyclic import: {}", chain.join(" -> ")),
+            ModuleError::UnknownExport(name, module) => write!(f, "module \"{}\" has no export named `{}`", module, name),
+            ModuleError::Redefined(name) => write!(f, "`{}` is defined more than once", name),
+        }
+    }
+}
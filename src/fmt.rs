@@ -0,0 +1,479 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    errors::ParseFinalError,
+    lexer::Lexer,
+    nodes::*,
+    parser::Parser,
+    tokens::{Keyword, Location, Separator, Token},
+};
+
+const INDENT: &str = "    ";
+// Instruments whose settings fit in this many columns are written on one line.
+const MAX_WIDTH: usize = 100;
+
+enum Item<'a, 'src> {
+    Import(&'a ImportDeclarationNode<'src>),
+    Meta(&'a MetaDeclarationNode<'src>),
+    Staff(&'a StaffDeclarationNode<'src>),
+    Instrument(&'a InstrumentDeclarationNode<'src>),
+}
+
+// Reprints a source file in canonical form. A comment stays on its own line before the
+// statement that follows it, or at the end of the line it was written on.
+pub fn format(source: &str) -> Result<String, Vec<ParseFinalError>> {
+    let (program, errors) = Parser::new(Lexer::new(source)).parse();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.by_ref().map_while(Result::ok).collect();
+    let mut formatter = Formatter::new(source, tokens, lexer.comments());
+    formatter.program(&program);
+    Ok(formatter.out)
+}
+
+struct Formatter<'src> {
+    source: &'src str,
+    tokens: Vec<Token<'src>>,
+    // The closing brace of every block, by the offset of its opening brace.
+    closes: HashMap<usize, Location>,
+    comments: Vec<(Location, &'src str)>,
+    comment: usize,
+    indent: usize,
+    out: String,
+}
+
+impl<'src> Formatter<'src> {
+    fn new(source: &'src str, tokens: Vec<Token<'src>>, comments: Vec<(Location, &'src str)>) -> Self {
+        let mut closes = HashMap::new();
+        let mut open = vec![];
+        for token in &tokens {
+            match token {
+                Token::Separator(Separator::LCurly, location) => open.push(location.span.start),
+                Token::Separator(Separator::RCurly, location) => {
+                    if let Some(start) = open.pop() {
+                        closes.insert(start, *location);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self { source, tokens, closes, comments, comment: 0, indent: 0, out: String::new() }
+    }
+    fn program(&mut self, program: &ProgramNode<'src>) {
+        let mut items: Vec<_> = program.imports.iter().map(Item::Import).collect();
+        // A file without a meta block parses to an empty one.
+        if !program.meta.configs.is_empty() {
+            items.push(Item::Meta(&program.meta));
+        }
+        for declaration in &program.declarations {
+            items.extend(declaration.staff.as_ref().map(Item::Staff));
+            items.extend(declaration.instrument.as_ref().map(Item::Instrument));
+        }
+        let starts: Vec<_> = items.iter().map(|item| self.start(item)).collect();
+        let inline: Vec<_> = items
+            .iter()
+            .map(|item| match item {
+                Item::Instrument(instrument) => self.inline(instrument),
+                _ => None,
+            })
+            .collect();
+        for (index, item) in items.iter().enumerate() {
+            let limit = starts.get(index + 1).map_or(usize::MAX, |start| start.span.start);
+            // Imports and one-line instruments may be grouped, everything else is set apart.
+            let grouped = index > 0
+                && (matches!((&items[index - 1], item), (Item::Import(_), Item::Import(_)))
+                    || (inline[index - 1].is_some() && inline[index].is_some()));
+            if index > 0 && !grouped {
+                self.blank();
+            }
+            match item {
+                Item::Import(import) => self.line(starts[index], &import.to_string(), limit),
+                Item::Meta(meta) => {
+                    let close = self.open("meta", starts[index], meta.configs.first().map(|call| start(&call.identifier)));
+                    self.calls(&meta.configs, close);
+                    self.close(close, limit);
+                }
+                Item::Staff(staff) => self.staff(staff, starts[index], limit),
+                Item::Instrument(instrument) => match &inline[index] {
+                    Some(text) => self.line(starts[index], text, limit),
+                    None => {
+                        let head = format!("instrument {}", instrument.identifier);
+                        let close = self.open(&head, starts[index], instrument.configs.first().map(|call| start(&call.identifier)));
+                        self.calls(&instrument.configs, close);
+                        self.close(close, limit);
+                    }
+                },
+            }
+        }
+        self.flush(usize::MAX);
+    }
+    fn start(&self, item: &Item) -> Location {
+        match item {
+            Item::Import(import) => self.keyword_before(Keyword::Import, &import.source),
+            Item::Meta(meta) => self.keyword_before(Keyword::Meta, &meta.configs[0].identifier),
+            Item::Staff(staff) => self.keyword_before(Keyword::Staff, &staff.identifier),
+            Item::Instrument(instrument) => self.keyword_before(Keyword::Instrument, &instrument.identifier),
+        }
+    }
+    // The one-line form of an instrument, unless it is too long or has comments inside.
+    fn inline(&self, instrument: &InstrumentDeclarationNode) -> Option<String> {
+        let keyword = self.keyword_before(Keyword::Instrument, &instrument.identifier);
+        let close = self.closing(keyword);
+        if self.comments.iter().any(|(location, _)| (keyword.span.start..close.span.start).contains(&location.span.start)) {
+            return None;
+        }
+        let text = match instrument.configs.as_slice() {
+            [] => format!("instrument {} {{}}", instrument.identifier),
+            configs => {
+                let configs: Vec<_> = configs.iter().map(|call| self.call(call)).collect();
+                format!("instrument {} {{ {} }}", instrument.identifier, configs.join(" "))
+            }
+        };
+        Some(text).filter(|text| text.chars().count() <= MAX_WIDTH)
+    }
+    fn staff(&mut self, staff: &StaffDeclarationNode<'src>, keyword: Location, limit: usize) {
+        let mut head = format!("staff {} is {} in {}", staff.identifier, self.call(&staff.staff_type), staff.signature);
+        for option in &staff.options {
+            let _ = write!(head, " with {}", self.call(option));
+        }
+        let first = staff.statements.first().map(staff_statement_start);
+        let pickup_start = staff.pickup.as_ref().map(|pickup| pickup.calls.first().map_or(start(&pickup.keyword), |call| start(&call.identifier)));
+        let close = self.open(&head, keyword, pickup_start.or(first));
+        if let Some(pickup) = &staff.pickup {
+            self.calls(&pickup.calls, pickup.keyword.location());
+            self.block("pickup", pickup.keyword.location(), &pickup.block, first.unwrap_or(close.span.start));
+        }
+        self.staff_statements(&staff.statements, close.span.start);
+        self.close(close, limit);
+    }
+    fn staff_statements(&mut self, statements: &[StaffStatementNode<'src>], limit: usize) {
+        for (index, statement) in statements.iter().enumerate() {
+            let limit = statements.get(index + 1).map_or(limit, staff_statement_start);
+            if let Some(measure) = &statement.measure {
+                self.block("measure", measure.keyword.location(), &measure.block, limit);
+            }
+            if let Some(call) = &statement.call {
+                self.line(call.identifier.location(), &self.call(call), limit);
+            }
+            if let Some(time) = &statement.time {
+                self.line(time.keyword.location(), &format!("time {}", time.signature), limit);
+            }
+            if let Some(repeat) = &statement.repeat {
+                self.repeat(repeat, limit);
+            }
+        }
+    }
+    fn repeat(&mut self, repeat: &RepeatNode<'src>, limit: usize) {
+        let head = match &repeat.times {
+            Some(times) => format!("repeat {}", times),
+            None => "repeat".to_string(),
+        };
+        let first = repeat.statements.first().map(staff_statement_start);
+        let endings = repeat.endings.first().map(|ending| start(&ending.keyword));
+        let close = self.open(&head, repeat.keyword.location(), first.or(endings));
+        self.staff_statements(&repeat.statements, endings.unwrap_or(close.span.start));
+        for (index, ending) in repeat.endings.iter().enumerate() {
+            let passes: Vec<_> = ending.passes.iter().map(Token::to_string).collect();
+            let head = format!("ending {}", passes.join(", "));
+            let ending_close = self.open(&head, ending.keyword.location(), ending.statements.first().map(staff_statement_start));
+            self.staff_statements(&ending.statements, ending_close.span.start);
+            let limit = repeat.endings.get(index + 1).map_or(close.span.start, |ending| start(&ending.keyword));
+            self.close(ending_close, limit);
+        }
+        self.close(close, limit);
+    }
+    fn block(&mut self, head: &str, location: Location, block: &BlockNode<'src>, limit: usize) {
+        let close = self.open(head, location, block.statements.first().map(block_statement_start));
+        for (index, statement) in block.statements.iter().enumerate() {
+            let limit = block.statements.get(index + 1).map_or(close.span.start, block_statement_start);
+            if let Some(call) = &statement.call {
+                let mut text = self.call(&call.call);
+                for with in &call.with {
+                    match (&with.identifier, &with.call) {
+                        (Some(identifier), _) => {
+                            let _ = write!(text, " with {}", identifier);
+                        }
+                        (_, Some(modifier)) => {
+                            let _ = write!(text, " with {}", self.call(modifier));
+                        }
+                        _ => {}
+                    }
+                }
+                self.line(call.call.identifier.location(), &text, limit);
+            }
+            if let Some(tuplet) = &statement.tuplet {
+                self.block(&tuplet.head.to_string(), tuplet.head.location(), &tuplet.block, limit);
+            }
+            if let Some(voice) = &statement.voice {
+                self.block(&format!("voice {}", voice.number), voice.keyword.location(), &voice.block, limit);
+            }
+        }
+        self.close(close, limit);
+    }
+    fn calls(&mut self, calls: &[CallNode<'src>], close: Location) {
+        for (index, call) in calls.iter().enumerate() {
+            let limit = calls.get(index + 1).map_or(close.span.start, |call| start(&call.identifier));
+            self.line(call.identifier.location(), &self.call(call), limit);
+        }
+    }
+    fn call(&self, call: &CallNode) -> String {
+        let arguments: Vec<_> = call.arguments.iter().map(|argument| self.token(&argument.argument)).collect();
+        format!("{}({})", call.identifier, arguments.join(", "))
+    }
+    // Notes keep their spelling, which `Display` would normalise.
+    fn token(&self, token: &Token) -> String {
+        match token {
+            Token::Note(_, _, _, location) => self.source[location.span.start..location.span.end].to_string(),
+            token => token.to_string(),
+        }
+    }
+    // Writes `head {` and the comments that follow it on the same line, up to the first statement.
+    // Returns the closing brace.
+    fn open(&mut self, head: &str, location: Location, first: Option<usize>) -> Location {
+        let close = self.closing(location);
+        self.begin(location.span.start);
+        self.out.push_str(head);
+        self.out.push_str(" {");
+        self.end(location.line, first.unwrap_or(close.span.start));
+        self.indent += 1;
+        close
+    }
+    fn close(&mut self, close: Location, limit: usize) {
+        self.flush(close.span.start);
+        self.indent -= 1;
+        self.pad();
+        self.out.push('}');
+        self.end(close.line, limit);
+    }
+    fn line(&mut self, location: Location, text: &str, limit: usize) {
+        self.begin(location.span.start);
+        self.out.push_str(text);
+        self.end(location.line, limit);
+    }
+    // Starts the line of a statement at `offset`, after the comments that come before it.
+    fn begin(&mut self, offset: usize) {
+        self.flush(offset);
+        self.separate(offset);
+        self.pad();
+    }
+    // Finishes a line, taking along the comments written after it on the same line.
+    fn end(&mut self, line: usize, limit: usize) {
+        while let Some(&(location, text)) = self.comments.get(self.comment) {
+            if location.line != line || location.span.start >= limit {
+                break;
+            }
+            self.out.push(' ');
+            self.out.push_str(text);
+            self.comment += 1;
+        }
+        self.out.push('\n');
+    }
+    // Writes the comments before `offset` on lines of their own.
+    fn flush(&mut self, offset: usize) {
+        while let Some(&(location, text)) = self.comments.get(self.comment) {
+            if location.span.start >= offset {
+                break;
+            }
+            self.separate(location.span.start);
+            self.pad();
+            self.out.push_str(text);
+            self.out.push('\n');
+            self.comment += 1;
+        }
+    }
+    // Keeps a single blank line where the source had one or more, except at the start of a block.
+    fn separate(&mut self, offset: usize) {
+        let gap = &self.source[self.source[..offset].trim_end().len()..offset];
+        if gap.matches('\n').count() > 1 && !self.out.ends_with("{\n") {
+            self.blank();
+        }
+    }
+    fn blank(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+    fn pad(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+    // The closing brace of the first block opened after `location`.
+    fn closing(&self, location: Location) -> Location {
+        let open = self.tokens.iter().find_map(|token| match token {
+            Token::Separator(Separator::LCurly, open) if open.span.start > location.span.start => Some(open.span.start),
+            _ => None,
+        });
+        open.and_then(|open| self.closes.get(&open)).copied().unwrap_or(location)
+    }
+    // The nearest `keyword` before `token`, which the syntax tree does not keep for every node.
+    fn keyword_before(&self, keyword: Keyword, token: &Token) -> Location {
+        let offset = start(token);
+        self.tokens
+            .iter()
+            .rev()
+            .find(|candidate| start(candidate) < offset && matches!(candidate, Token::Keyword(found, _) if *found == keyword))
+            .map_or(token.location(), Token::location)
+    }
+}
+
+fn start(token: &Token) -> usize {
+    token.location().span.start
+}
+
+fn staff_statement_start(statement: &StaffStatementNode) -> usize {
+    match statement {
+        StaffStatementNode { measure: Some(measure), .. } => start(&measure.keyword),
+        StaffStatementNode { call: Some(call), .. } => start(&call.identifier),
+        StaffStatementNode { time: Some(time), .. } => start(&time.keyword),
+        StaffStatementNode { repeat: Some(repeat), .. } => start(&repeat.keyword),
+        _ => 0,
+    }
+}
+
+fn block_statement_start(statement: &BlockStatementNode) -> usize {
+    match statement {
+        BlockStatementNode { call: Some(call), .. } => start(&call.call.identifier),
+        BlockStatementNode { tuplet: Some(tuplet), .. } => start(&tuplet.head),
+        BlockStatementNode { voice: Some(voice), .. } => start(&voice.keyword),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMENTS: &str = r#"// Header comment
+import { piano } from "std/instruments" // trailing import
+
+/// The right hand.
+staff rh is treble(piano) in [4/4] { // after open
+    bpm(90); // tempo
+    /// Letter A
+    measure {
+        // before the first note
+        1(C4) with p /* block */
+    }
+    repeat 2 {
+        measure { 1(D4) } // after measure
+        // inside the repeat
+    }
+    repeat {
+        measure { 1(E4) }
+        ending 1 {
+            // first time
+            measure { 1(F4) } // end of ending
+        }
+        ending 2 { measure { 1(G4) } }
+    }
+    measure {
+        voice 1 {
+            1(C5) // top
+        }
+        voice 2 { /* under */ 2(C4) 2(D4) }
+    }
+    // before the close
+}
+// at the end
+"#;
+
+    #[test]
+    fn formats_old_example() {
+        let expected = r#"import { trumpet } from "std/instruments"
+
+meta {
+    title("Test")
+    description("Testing the language")
+    composer("Nebula")
+}
+
+staff t is treble(trumpet) in [3/4] {
+    bpm(100)
+
+    pickup {
+        8(C) with dot
+        16(C)
+    }
+
+    measure {
+        4(D)
+        4(C)
+        4(F)
+    }
+
+    measure {
+        2(E)
+        4(rest)
+    }
+}
+"#;
+        assert_eq!(format(include_str!("../example/old.tn")).unwrap(), expected);
+    }
+
+    #[test]
+    fn comments_survive_in_nested_blocks() {
+        let expected = r#"// Header comment
+import { piano } from "std/instruments" // trailing import
+
+/// The right hand.
+staff rh is treble(piano) in [4/4] { // after open
+    bpm(90) // tempo
+    /// Letter A
+    measure {
+        // before the first note
+        1(C4) with p /* block */
+    }
+    repeat 2 {
+        measure {
+            1(D4)
+        } // after measure
+        // inside the repeat
+    }
+    repeat {
+        measure {
+            1(E4)
+        }
+        ending 1 {
+            // first time
+            measure {
+                1(F4)
+            } // end of ending
+        }
+        ending 2 {
+            measure {
+                1(G4)
+            }
+        }
+    }
+    measure {
+        voice 1 {
+            1(C5) // top
+        }
+        voice 2 { /* under */
+            2(C4)
+            2(D4)
+        }
+    }
+    // before the close
+}
+// at the end
+"#;
+        assert_eq!(format(COMMENTS).unwrap(), expected);
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for source in [include_str!("../example/old.tn"), include_str!("../example/test.tn"), COMMENTS] {
+            let once = format(source).unwrap();
+            assert_eq!(format(&once).unwrap(), once);
+        }
+    }
+
+    #[test]
+    fn parse_errors_are_returned() {
+        assert!(format("staff s is treble() in [4/4] { measure { 1(C) }").is_err());
+    }
+}
@@ -1,87 +1,220 @@
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 
-use crate::tokens::{Keyword, Separator, Token, Location};
+use crate::{
+    errors::{LexError, LexResult},
+    tokens::{Keyword, Note, Separator, Span, Token, Location},
+};
 pub struct Lexer<'src> {
+    // Byte offset of the next unread character, and the line and column it sits on.
     pos: Cell<usize>,
     line: Cell<usize>,
     col: Cell<usize>,
+    // Where the token being lexed started.
+    start: Cell<(usize, usize, usize)>,
+    file: usize,
     source: &'src str,
+    docs: RefCell<Vec<(usize, Vec<&'src str>)>>,
+    // Every comment in the source, for tools that reprint it.
+    comments: RefCell<Vec<(Location, &'src str)>>,
+    finished: Cell<bool>,
+}
+
+// Lexes a whole source, stopping at the first error. The last token is always `Token::EOF`.
+pub fn tokenize(source: &str) -> LexResult<Vec<Token<'_>>> {
+    Lexer::new(source).collect()
 }
 
 impl<'src> Lexer<'src> {
     pub fn new(source: &'src str) -> Self {
-        Self { source, pos: 0.into(), line: 1.into(), col: 0.into() }
+        Self::with_file(source, 0)
     }
-    pub fn lex(&self) -> Option<Token<'src>> {
-        let value = if self.pos.get() == 0 {
-            self.peek()?
-        } else {
-            self.advance_filtered()?
+    pub fn with_file(source: &'src str, file: usize) -> Self {
+        Self { source, file, pos: 0.into(), line: 1.into(), col: 1.into(), start: (0, 1, 1).into(), docs: vec![].into(), comments: vec![].into(), finished: false.into() }
+    }
+    // Returns `Token::EOF` at the end of the input, then `None`.
+    pub fn lex(&self) -> Option<LexResult<Token<'src>>> {
+        let docs = match self.skip_trivia() {
+            Ok(docs) => docs,
+            Err(err) => return Some(Err(err)),
         };
-        match value {
-            "\"" => self.process_literal(),
-            "0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" => self.process_number(),
-            "[" => self.process_signature(),
-            "(" | ")" | "{" | "}" | ";" | "," => self.process_separator(),
-            _ => self.process_word(),
+        self.start.set((self.pos.get(), self.line.get(), self.col.get()));
+        if !docs.is_empty() {
+            self.docs.borrow_mut().push((self.pos.get(), docs));
         }
+        let Some(next) = self.peek() else {
+            return (!self.finished.replace(true)).then(|| Ok(Token::EOF(self.location())));
+        };
+        Some(match next {
+            '"' => self.process_literal(),
+            '0'..='9' => self.process_ratio(),
+            '[' => self.process_signature(),
+            '(' | ')' | '{' | '}' | ';' | ',' => Ok(self.process_separator()),
+            c if Self::is_word(c) => self.process_word(),
+            c => {
+                self.bump();
+                Err(self.fail(LexError::InvalidCharacter(c)))
+            }
+        })
+    }
+    pub fn location(&self) -> Location {
+        if self.is_done() {
+            let end = self.source.len();
+            return Location { file: self.file, line: self.line.get(), col: self.col.get(), span: Span { start: end, end } };
+        }
+        self.loc()
+    }
+    // The doc comment lines written directly before the token at `location`.
+    pub fn docs(&self, location: Location) -> Vec<&'src str> {
+        let docs = self.docs.borrow();
+        docs.iter().find(|(start, _)| *start == location.span.start).map_or(vec![], |(_, lines)| lines.clone())
+    }
+    // The comments lexed so far, in source order.
+    pub fn comments(&self) -> Vec<(Location, &'src str)> {
+        self.comments.borrow().clone()
+    }
+    pub fn text(&self, location: Location) -> &'src str {
+        &self.source[location.span.start..location.span.end]
+    }
+    pub fn is_done(&self) -> bool {
+        self.pos.get() >= self.source.len()
+    }
+    fn fail(&self, error: LexError) -> (LexError, Location) {
+        (error, self.loc())
     }
     fn loc(&self) -> Location {
-        Location { line: self.line.get(), col: self.col.get() }
+        let (start, line, col) = self.start.get();
+        Location { file: self.file, line, col, span: Span { start, end: self.pos.get() } }
     }
-    fn process_signature(&self) -> Option<Token<'src>> {
-        self.advance_filtered();
-        let top = self.process_number();
-        if !matches!(self.advance_filtered(), Some("/")) {
-            return None;
+    // Skips whitespace and comments. Doc comments are returned for the token that follows them.
+    fn skip_trivia(&self) -> LexResult<Vec<&'src str>> {
+        let mut docs = vec![];
+        loop {
+            let start = (self.pos.get(), self.line.get(), self.col.get());
+            match (self.peek(), self.peek_second()) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                (Some('/'), Some('/')) => docs.extend(self.line_comment()),
+                (Some('/'), Some('*')) => {
+                    if !self.block_comment() {
+                        self.start.set(start);
+                        return Err(self.fail(LexError::UnterminatedComment));
+                    }
+                    docs.clear();
+                }
+                _ => return Ok(docs),
+            }
+            self.comment(start);
         }
-        self.advance_filtered();
-        let bottom = self.process_number();
-        if !matches!(self.advance_filtered(), Some("]")) {
-            return None;
+    }
+    fn comment(&self, (start, line, col): (usize, usize, usize)) {
+        let location = Location { file: self.file, line, col, span: Span { start, end: self.pos.get() } };
+        let text = self.source[start..self.pos.get()].trim_end_matches('\r');
+        self.comments.borrow_mut().push((location, text));
+    }
+    // Skips to the end of the line. Returns the text of a `///` doc comment.
+    fn line_comment(&self) -> Option<&'src str> {
+        let start = self.pos.get();
+        while self.peek().is_some_and(|c| c != '\n') {
+            self.bump();
         }
-        match (top, bottom) {
-            (Some(Token::Number(top, _)), Some(Token::Number(bottom, _))) => Some(Token::Signature(top, bottom, self.loc())),
-            _ => None
+        let text = self.source[start..self.pos.get()].trim_end_matches('\r');
+        let doc = text.strip_prefix("///").filter(|doc| !doc.starts_with('/'))?;
+        Some(doc.strip_prefix(' ').unwrap_or(doc))
+    }
+    // Skips a block comment, including any comments nested inside it. Returns false when the
+    // input ends before the comment is closed.
+    fn block_comment(&self) -> bool {
+        let mut depth = 0;
+        while let Some(c) = self.bump() {
+            match (c, self.peek()) {
+                ('/', Some('*')) => {
+                    self.bump();
+                    depth += 1;
+                }
+                ('*', Some('/')) => {
+                    self.bump();
+                    depth -= 1;
+                    if depth == 0 {
+                        return true;
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+    fn process_signature(&self) -> LexResult<Token<'src>> {
+        self.bump();
+        self.skip_whitespace();
+        let top = self.process_number()?;
+        self.skip_whitespace();
+        let slash = self.bump() == Some('/');
+        self.skip_whitespace();
+        let bottom = self.process_number()?;
+        self.skip_whitespace();
+        match (top, slash, bottom, self.peek()) {
+            (Some(top), true, Some(bottom), Some(']')) => {
+                self.bump();
+                Ok(Token::Signature(top, bottom, self.loc()))
+            }
+            _ => Err(self.fail(LexError::BadSignature)),
         }
     }
-    fn process_number(&self) -> Option<Token<'src>> {
-        let pos = self.pos.get();
-        while let Some("0" | "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9") = self.advance() {
+    // Returns `None` when there are no digits to read.
+    fn process_number(&self) -> LexResult<Option<u32>> {
+        let start = self.pos.get();
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
         }
-        self.pos.set(self.pos.get() - 1);
-        self.peek()?;
-        let num: u32 = self.source.get(pos..=self.pos.get())?.parse().ok()?;
-        Some(Token::Number(num, self.loc()))
-    }
-    fn process_literal(&self) -> Option<Token<'src>> {
-        let pos = self.pos.get();
-        while !matches!(self.advance(), Some("\"")) {}
-        self.peek()?;
-        let literal = self.source.get(pos + 1..self.pos.get())?;
-        Some(Token::Literal(literal, self.loc()))
-    }
-    fn process_separator(&self) -> Option<Token<'src>> {
-        match self.peek() {
-            Some("(") => Some(Token::Separator(Separator::LParan, self.loc())),
-            Some(")") => Some(Token::Separator(Separator::RParan, self.loc())),
-            Some("{") => Some(Token::Separator(Separator::LCurly, self.loc())),
-            Some("}") => Some(Token::Separator(Separator::RCurly, self.loc())),
-            Some(";") => Some(Token::Separator(Separator::Semicolon, self.loc())),
-            Some(",") => Some(Token::Separator(Separator::Comma, self.loc())),
-            _ => None,
+        let digits = &self.source[start..self.pos.get()];
+        if digits.is_empty() {
+            return Ok(None);
         }
+        digits.parse().map(Some).map_err(|_| self.fail(LexError::NumberOverflow(digits.to_string())))
+    }
+    fn process_ratio(&self) -> LexResult<Token<'src>> {
+        let actual = self.process_number()?.unwrap_or_default();
+        if self.peek() != Some(':') || !self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            return Ok(Token::Number(actual, self.loc()));
+        }
+        self.bump();
+        let normal = self.process_number()?.unwrap_or_default();
+        Ok(Token::Ratio(actual, normal, self.loc()))
+    }
+    fn process_literal(&self) -> LexResult<Token<'src>> {
+        self.bump();
+        let start = self.pos.get();
+        loop {
+            match self.bump() {
+                Some('"') => break,
+                Some(_) => continue,
+                None => return Err(self.fail(LexError::UnterminatedLiteral)),
+            }
+        }
+        let literal = &self.source[start..self.pos.get() - 1];
+        Ok(Token::Literal(literal, self.loc()))
+    }
+    fn process_separator(&self) -> Token<'src> {
+        let separator = match self.bump() {
+            Some('(') => Separator::LParan,
+            Some(')') => Separator::RParan,
+            Some('{') => Separator::LCurly,
+            Some('}') => Separator::RCurly,
+            Some(';') => Separator::Semicolon,
+            _ => Separator::Comma,
+        };
+        Token::Separator(separator, self.loc())
     }
-    fn process_word(&self) -> Option<Token<'src>> {
-        let pos = self.pos.get();
-        while !matches!(
-            self.advance(),
-            Some(" " | "\t" | "\n" | "\r" | "(" | ")" | "{" | "}" | ";" | ",")
-        ) {}
-        self.pos.set(self.pos.get() - 1);
-        self.peek()?;
-        let literal = self.source.get(pos..=self.pos.get())?;
-        let token = match literal {
+    fn process_word(&self) -> LexResult<Token<'src>> {
+        let start = self.pos.get();
+        // A comment may follow a word without a space in between.
+        while self.peek().is_some_and(Self::is_word) && !matches!((self.peek(), self.peek_second()), (Some('/'), Some('/' | '*'))) {
+            self.bump();
+        }
+        let literal = &self.source[start..self.pos.get()];
+        Ok(match literal {
             "import" => Token::Keyword(Keyword::Import, self.loc()),
             "meta" => Token::Keyword(Keyword::Meta, self.loc()),
             "staff" => Token::Keyword(Keyword::Staff, self.loc()),
@@ -91,43 +224,134 @@ impl<'src> Lexer<'src> {
             "with" => Token::Keyword(Keyword::With, self.loc()),
             "is" => Token::Keyword(Keyword::Is, self.loc()),
             "in" => Token::Keyword(Keyword::In, self.loc()),
-            _ => Token::Identifier(literal, self.loc()),
-        };
-        Some(token)
+            "instrument" => Token::Keyword(Keyword::Instrument, self.loc()),
+            "time" => Token::Keyword(Keyword::Time, self.loc()),
+            "repeat" => Token::Keyword(Keyword::Repeat, self.loc()),
+            "ending" => Token::Keyword(Keyword::Ending, self.loc()),
+            "voice" => Token::Keyword(Keyword::Voice, self.loc()),
+            // Words like `G7b9` look like notes but are left as identifiers for chord symbols.
+            _ => match Note::parse(literal).filter(|_| Self::is_note_like(literal)) {
+                Some((note, octave, explicit)) => Token::Note(note, octave, explicit, self.loc()),
+                None => Token::Identifier(literal, self.loc()),
+            },
+        })
     }
-    fn advance(&self) -> Option<&'src str> {
-        self.pos.set(self.pos.get() + 1);
-        self.col.set(self.col.get() + 1);
-        self.peek()
+    fn skip_whitespace(&self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
     }
-    fn advance_filtered(&self) -> Option<&'src str> {
-        loop {
-            if let Some(value) = self.advance() {
-                if Self::is_newline(value) {
-                    self.line.set(self.line.get() + 1);
-                    self.col.set(0);
-                }
-                if Self::is_whitespace(value) {
-                    continue;
-                }
-                break Some(value);
-            }
-            return None;
+    fn bump(&self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos.set(self.pos.get() + c.len_utf8());
+        if c == '\n' {
+            self.line.set(self.line.get() + 1);
+            self.col.set(1);
+        } else {
+            self.col.set(self.col.get() + 1);
         }
+        Some(c)
     }
-    fn peek(&self) -> Option<&'src str> {
-        self.source.get(self.pos.get()..=self.pos.get())
+    fn peek(&self) -> Option<char> {
+        self.source[self.pos.get()..].chars().next()
     }
-    fn is_whitespace(value: &'src str) -> bool {
-        match value {
-            " " | "\t" | "\n" | "\r" => true,
-            _ => false,
-        }
+    fn peek_second(&self) -> Option<char> {
+        self.source[self.pos.get()..].chars().nth(1)
     }
-    fn is_newline(value: &'src str) -> bool {
-        match value {
-            "\n" => true,
-            _ => false,
-        }
+    // Chord symbols such as `Bø7`, `C°`, `CΔ9` and `C6/9` are single words.
+    fn is_word(c: char) -> bool {
+        c.is_alphanumeric() || matches!(c, '_' | '#' | '/' | '+' | '-' | '°' | '♯' | '♭' | '♮' | '𝄪' | '𝄫')
+    }
+    fn is_note_like(value: &'src str) -> bool {
+        let mut chars = value.chars();
+        matches!(chars.next(), Some('A'..='Z'))
+            && chars.all(|c| matches!(c, '#' | 'b' | 'n' | '♯' | '♭' | '♮' | '𝄪' | '𝄫' | '0'..='9'))
+    }
+}
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = LexResult<Token<'src>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_accidentals() {
+        let tokens = tokenize("F♯4 B♭3 C𝄪5 E𝄫2 A♮4").unwrap();
+        assert!(matches!(tokens[0], Token::Note(Note::Fs, 4, true, _)));
+        assert!(matches!(tokens[1], Token::Note(Note::Bb, 3, true, _)));
+        assert!(matches!(tokens[2], Token::Note(Note::Css, 5, true, _)));
+        assert!(matches!(tokens[3], Token::Note(Note::Ebb, 2, true, _)));
+        assert!(matches!(tokens[4], Token::Note(Note::A, 4, true, _)));
+    }
+
+    #[test]
+    fn spans_count_bytes_and_columns_count_characters() {
+        let source = "title(\"Déjà vu\") B♭3";
+        let tokens = tokenize(source).unwrap();
+        let literal = tokens[2].location();
+        assert_eq!(&source[literal.span.start..literal.span.end], "\"Déjà vu\"");
+        let note = tokens[4].location();
+        assert_eq!(&source[note.span.start..note.span.end], "B♭3");
+        assert_eq!((note.line, note.col), (1, 18));
+    }
+
+    #[test]
+    fn words_end_at_comments() {
+        let tokens = tokenize("staccato// note\nlegato/* slur */tenuto").unwrap();
+        assert!(matches!(tokens[0], Token::Identifier("staccato", _)));
+        assert!(matches!(tokens[1], Token::Identifier("legato", _)));
+        assert!(matches!(tokens[2], Token::Identifier("tenuto", _)));
+        assert!(matches!(tokens[2].location(), Location { line: 2, col: 17, .. }));
+    }
+
+    #[test]
+    fn chord_symbols_stay_single_words() {
+        let tokens = tokenize("Bø7 C6/9 G7b9 F#m7").unwrap();
+        assert!(matches!(tokens[0], Token::Identifier("Bø7", _)));
+        assert!(matches!(tokens[1], Token::Identifier("C6/9", _)));
+        assert!(matches!(tokens[2], Token::Identifier("G7b9", _)));
+        assert!(matches!(tokens[3], Token::Identifier("F#m7", _)));
+    }
+
+    #[test]
+    fn names_shaped_like_notes() {
+        let tokens = tokenize("staff V1 H4 T2").unwrap();
+        assert!(matches!(tokens[1], Token::Identifier("V1", _)));
+        assert!(matches!(tokens[2], Token::Identifier("H4", _)));
+        assert!(matches!(tokens[3], Token::Identifier("T2", _)));
+    }
+
+    #[test]
+    fn ends_with_one_eof() {
+        let tokens = tokenize("measure { }\n").unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert!(matches!(tokens[3], Token::EOF(Location { line: 2, col: 1, span: Span { start: 12, end: 12 }, .. })));
+        assert!(matches!(tokenize("").unwrap().as_slice(), [Token::EOF(_)]));
+        assert!(matches!(tokenize("// only a comment").unwrap().as_slice(), [Token::EOF(_)]));
+    }
+
+    #[test]
+    fn iterator_stops_after_eof() {
+        let mut lexer = Lexer::new("C4");
+        assert!(matches!(lexer.next(), Some(Ok(Token::Note(Note::C, 4, false, _)))));
+        assert!(matches!(lexer.next(), Some(Ok(Token::EOF(_)))));
+        assert!(lexer.next().is_none());
+        assert!(lexer.next().is_none());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(tokenize("measure /* open"), Err((LexError::UnterminatedComment, Location { col: 9, .. }))));
+        assert!(matches!(tokenize("title(\"open)"), Err((LexError::UnterminatedLiteral, _))));
+        assert!(matches!(tokenize("4(C) @"), Err((LexError::InvalidCharacter('@'), _))));
+        assert!(matches!(tokenize("[3 4]"), Err((LexError::BadSignature, _))));
+        assert!(matches!(tokenize("[3/4 {"), Err((LexError::BadSignature, _))));
     }
 }
@@ -0,0 +1,738 @@
+use crate::{
+    articulations,
+    chords::{ChordSymbol, Voicing, VoicingStyle},
+    errors::{CompileError::*, CompileFinalResult, CompileResult},
+    modules::Scope,
+    nodes::*,
+    score::*,
+    tokens::{Location, Note, Token},
+};
+
+pub fn lower<'src>(program: &ProgramNode<'src>, scope: &Scope<'_, 'src>) -> CompileFinalResult<Score<'src>> {
+    let mut score = Score {
+        title: None,
+        composer: None,
+        description: None,
+        staves: vec![],
+        tempo: TempoMap::default(),
+        velocities: VelocityTable::default(),
+    };
+    for config in &program.meta.configs {
+        match config.identifier {
+            Token::Identifier("title", _) => score.title = Some(literal_argument(config)?),
+            Token::Identifier("composer", _) => score.composer = Some(literal_argument(config)?),
+            Token::Identifier("description", _) => score.description = Some(literal_argument(config)?),
+            Token::Identifier("velocity", _) => {
+                let (dynamic, velocity) = lower_velocity(config)?;
+                score.velocities.set(dynamic, velocity);
+            }
+            token => return Err((UnknownMeta(token.to_string()), token.location())),
+        }
+    }
+    lower_staves(program, scope, &mut score.staves)?;
+    Ok(score)
+}
+
+// Lowers a single source with no imports, for tests of the passes that run on a score.
+#[cfg(test)]
+pub fn compile(source: &str) -> CompileFinalResult<Score<'_>> {
+    use crate::{lexer::Lexer, modules::Module, parser::Parser};
+    let (program, errors) = Parser::new(Lexer::new(source)).parse();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let modules = [Module { program, imports: vec![] }];
+    let scope = crate::modules::scope(&modules, 0).expect("a single module has nothing to resolve");
+    lower(&modules[0].program, &scope)
+}
+
+// Imported staffs come first, each lowered against the scope of the module that declared it.
+fn lower_staves<'src>(program: &ProgramNode<'src>, scope: &Scope<'_, 'src>, staves: &mut Vec<Staff<'src>>) -> CompileFinalResult<()> {
+    for (staff, staff_scope) in &scope.staffs {
+        staves.push(lower_staff(staff, staff_scope)?);
+    }
+    for staff in program.declarations.iter().filter_map(|declaration| declaration.staff.as_ref()) {
+        staves.push(lower_staff(staff, scope)?);
+    }
+    Ok(())
+}
+
+fn lower_staff<'src>(staff: &StaffDeclarationNode<'src>, scope: &Scope<'_, 'src>) -> CompileFinalResult<Staff<'src>> {
+    let (clef, instrument) = lower_staff_type(&staff.staff_type, scope)?;
+    let signature = lower_signature(&staff.signature)?;
+    let mut settings = Settings { voicing: Voicing::default(), key: Key::default(), signature };
+    for option in &staff.options {
+        match option.identifier {
+            Token::Identifier("key", _) => settings.key = lower_key(option)?,
+            token => return Err((UnknownDirective(token.to_string()), token.location())),
+        }
+    }
+    let mut lowering = StaffLowering { settings, directives: vec![], time: None, measures: vec![], navigation: Navigation::default(), jump: None, repeat: false };
+    let pickup = match &staff.pickup {
+        Some(pickup) => {
+            for call in &pickup.calls {
+                lowering.call(call)?;
+            }
+            let directives = std::mem::take(&mut lowering.directives);
+            Some(lower_block(&pickup.block, vec![], directives, lowering.settings, pickup.keyword.location())?)
+        }
+        None => None,
+    };
+    lowering.statements(&staff.statements)?;
+    if let Some(loc) = lowering.directives.first().map(|(_, loc)| *loc).or(lowering.time) {
+        return Err((DanglingDirective, loc));
+    }
+    lowering.check_jump()?;
+    let mut staff = Staff {
+        docs: staff.docs.clone(),
+        name: name(&staff.identifier),
+        clef,
+        instrument,
+        pickup,
+        measures: lowering.measures,
+        navigation: lowering.navigation,
+        location: staff.identifier.location(),
+    };
+    link_events(&mut staff)?;
+    Ok(staff)
+}
+
+// Staff state carried from one statement to the next, including into repeats and endings.
+struct StaffLowering<'src> {
+    settings: Settings,
+    directives: Vec<(Directive, Location)>,
+    time: Option<Location>,
+    measures: Vec<Measure<'src>>,
+    navigation: Navigation,
+    jump: Option<Location>,
+    repeat: bool,
+}
+
+impl<'src> StaffLowering<'src> {
+    fn statements(&mut self, statements: &[StaffStatementNode<'src>]) -> CompileFinalResult<()> {
+        for statement in statements {
+            if let Some(node) = &statement.time {
+                self.settings.signature = lower_signature(&node.signature)?;
+                self.time = Some(node.keyword.location());
+            }
+            if let Some(call) = &statement.call {
+                self.call(call)?;
+            }
+            if let Some(measure) = &statement.measure {
+                let directives = std::mem::take(&mut self.directives);
+                self.measures.push(lower_block(&measure.block, measure.docs.clone(), directives, self.settings, measure.keyword.location())?);
+                self.time = None;
+            }
+            if let Some(repeat) = &statement.repeat {
+                self.repeat(repeat)?;
+            }
+        }
+        Ok(())
+    }
+    fn call(&mut self, call: &CallNode<'src>) -> CompileFinalResult<()> {
+        match call.identifier {
+            // Voicings and keys only shape how pitches are lowered, so they are not kept as directives.
+            Token::Identifier("voicing", _) => self.settings.voicing = lower_voicing(call)?,
+            Token::Identifier("key", _) => self.settings.key = lower_key(call)?,
+            Token::Identifier(name @ ("segno" | "coda" | "tocoda" | "fine" | "dc" | "ds"), _) => self.mark(name, call)?,
+            _ => self.directives.push((lower_directive(call)?, call.identifier.location())),
+        }
+        Ok(())
+    }
+    // A repeat plays twice unless a count is given, or more often when its endings number more passes.
+    fn repeat(&mut self, node: &RepeatNode<'src>) -> CompileFinalResult<()> {
+        if self.repeat {
+            return Err((NestedRepeat, node.keyword.location()));
+        }
+        let passes = node.endings.iter().flat_map(|ending| &ending.passes);
+        let times = match node.times {
+            Some(Token::Number(times @ 2.., _)) => times,
+            Some(token @ Token::Number(times, _)) => return Err((BadRepeat(times), token.location())),
+            _ => passes.filter_map(|pass| if let Token::Number(pass, _) = pass { Some(*pass) } else { None }).max().unwrap_or(2).max(2),
+        };
+        self.repeat = true;
+        let start = self.measures.len();
+        self.statements(&node.statements)?;
+        let end = self.measures.len();
+        let mut endings: Vec<Ending> = vec![];
+        for ending in &node.endings {
+            let mut passes = vec![];
+            for token in &ending.passes {
+                let Token::Number(pass, loc) = *token else { continue };
+                if pass == 0 || pass > times {
+                    return Err((EndingOutOfRange(pass, times), loc));
+                }
+                if endings.iter().flat_map(|ending| &ending.passes).chain(&passes).any(|other| *other == pass) {
+                    return Err((DuplicateEnding(pass), loc));
+                }
+                passes.push(pass);
+            }
+            let start = self.measures.len();
+            self.statements(&ending.statements)?;
+            endings.push(Ending { passes, start, end: self.measures.len() });
+        }
+        self.navigation.repeats.push(Repeat { start, end, times, endings });
+        self.repeat = false;
+        Ok(())
+    }
+    // Marks sit between measures, so unlike directives they may close a staff.
+    fn mark(&mut self, name: &str, call: &CallNode) -> CompileFinalResult<()> {
+        let loc = call.identifier.location();
+        if self.repeat {
+            return Err((MarkInRepeat(name.to_string()), loc));
+        }
+        let at = self.measures.len();
+        let slot = match name {
+            "segno" => &mut self.navigation.segno,
+            "coda" => &mut self.navigation.coda,
+            "tocoda" => &mut self.navigation.to_coda,
+            "fine" => &mut self.navigation.fine,
+            _ => return self.jump(name, call),
+        };
+        if let [argument, ..] = call.arguments.as_slice() {
+            return Err((UnexpectedArgument(argument.argument.to_string()), argument.argument.location()));
+        }
+        if slot.replace(at).is_some() {
+            return Err((DuplicateMark(name.to_string()), loc));
+        }
+        Ok(())
+    }
+    // `dc()` and `ds()` play on to the end, `dc(fine)` stops at `fine()` and `dc(coda)` skips from `tocoda()` to `coda()`.
+    fn jump(&mut self, name: &str, call: &CallNode) -> CompileFinalResult<()> {
+        let loc = call.identifier.location();
+        let until = match call.arguments.as_slice() {
+            [] => JumpEnd::End,
+            [ArgumentNode { argument: Token::Identifier("fine", _) }] => JumpEnd::Fine,
+            [ArgumentNode { argument: Token::Identifier("coda", _) }] => JumpEnd::Coda,
+            [_, extra, ..] => return Err((UnexpectedArgument(extra.argument.to_string()), extra.argument.location())),
+            [argument] => return Err((UnknownJump(argument.argument.to_string()), argument.argument.location())),
+        };
+        if self.navigation.jump.is_some() {
+            return Err((DuplicateJump, loc));
+        }
+        let to = if name == "dc" { JumpTarget::Start } else { JumpTarget::Segno };
+        self.navigation.jump = Some(Jump { at: self.measures.len(), to, until });
+        self.jump = Some(loc);
+        Ok(())
+    }
+    fn check_jump(&self) -> CompileFinalResult<()> {
+        let (Some(jump), Some(loc)) = (self.navigation.jump, self.jump) else { return Ok(()) };
+        let navigation = &self.navigation;
+        let missing = [
+            (jump.to == JumpTarget::Segno && navigation.segno.is_none(), "segno"),
+            (jump.until == JumpEnd::Fine && navigation.fine.is_none(), "fine"),
+            (jump.until == JumpEnd::Coda && navigation.to_coda.is_none(), "tocoda"),
+            (jump.until == JumpEnd::Coda && navigation.coda.is_none(), "coda"),
+        ];
+        match missing.into_iter().find(|(missing, _)| *missing) {
+            Some((_, mark)) => Err((MissingMark(mark), loc)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Ties and slurs reach across measures, so they are resolved over each voice once the staff is lowered.
+fn link_events(staff: &mut Staff) -> CompileFinalResult<()> {
+    for number in staff.voices() {
+        link_voice(staff.voice_events_mut(number))?;
+    }
+    Ok(())
+}
+
+fn link_voice(mut events: Vec<&mut Event>) -> CompileFinalResult<()> {
+    for index in 0..events.len() {
+        if events[index].tie {
+            let tied = &events[index].pitches;
+            let matched = !tied.is_empty()
+                && events.get(index + 1).is_some_and(|next| tied.iter().all(|pitch| next.pitches.iter().any(|other| other.midi() == pitch.midi())));
+            if !matched {
+                return Err((UnmatchedTie, events[index].location));
+            }
+        }
+    }
+    let mut open: Option<Location> = None;
+    for event in &mut events {
+        for modifier in &event.modifiers {
+            match (modifier.name, open) {
+                ("slur", Some(_)) => return Err((NestedSlur, modifier.location)),
+                ("slur", None) => open = Some(modifier.location),
+                ("endslur", Some(_)) => open = None,
+                ("endslur", None) => return Err((UnmatchedSlur, modifier.location)),
+                _ => {}
+            }
+        }
+        event.legato = open.is_some();
+    }
+    match open {
+        Some(location) => Err((UnclosedSlur, location)),
+        None => Ok(()),
+    }
+}
+
+fn lower_staff_type<'src>(call: &CallNode<'src>, scope: &Scope<'_, 'src>) -> CompileFinalResult<(Clef, Option<Instrument<'src>>)> {
+    // `staff t is trumpet()` reads the instrument on its default clef.
+    if let (Token::Identifier(name, _), []) = (call.identifier, call.arguments.as_slice()) {
+        if let (Err(_), Some(declaration)) = (clef(&call.identifier), scope.instruments.get(name)) {
+            let instrument = lower_instrument(name, declaration)?;
+            return Ok((instrument.clef, Some(instrument)));
+        }
+    }
+    let clef = clef(&call.identifier)?;
+    let instrument = match call.arguments.as_slice() {
+        [] => None,
+        [ArgumentNode { argument: Token::Identifier(name, loc) }] => match scope.instruments.get(name) {
+            Some(declaration) => Some(lower_instrument(name, declaration)?),
+            None => return Err((UnknownInstrument(name.to_string()), *loc)),
+        },
+        [argument, ..] => return Err((UnexpectedArgument(argument.argument.to_string()), argument.argument.location())),
+    };
+    Ok((clef, instrument))
+}
+
+// Instruments are named by the staff that uses them, so aliases and imports keep the local name.
+fn lower_instrument<'src>(name: &'src str, declaration: &InstrumentDeclarationNode<'src>) -> CompileFinalResult<Instrument<'src>> {
+    let mut instrument = Instrument { name, program: 0, percussion: false, clef: Clef::Treble, range: None };
+    for config in &declaration.configs {
+        let loc = config.identifier.location();
+        match (config.identifier, config.arguments.as_slice()) {
+            (Token::Identifier(setting @ ("program" | "kit"), _), [ArgumentNode { argument: Token::Number(program, loc) }]) => {
+                if !(1..=128).contains(program) {
+                    return Err((BadProgram(*program), *loc));
+                }
+                instrument.program = (*program - 1) as u8;
+                instrument.percussion = setting == "kit";
+            }
+            (Token::Identifier("program" | "kit", _), _) => return Err((ExpectedNumber, loc)),
+            (Token::Identifier("clef", _), [ArgumentNode { argument }]) => instrument.clef = clef(argument)?,
+            (Token::Identifier("range", _), [low, high]) => {
+                let low = range_pitch(&low.argument)?;
+                let high = range_pitch(&high.argument)?;
+                instrument.range = Some((low, high));
+            }
+            (Token::Identifier("clef", _), [_, extra, ..]) | (Token::Identifier("range", _), [_, _, extra, ..]) => {
+                return Err((UnexpectedArgument(extra.argument.to_string()), extra.argument.location()))
+            }
+            (Token::Identifier("range", _), _) => return Err((ExpectedPitch, loc)),
+            (token, _) => return Err((UnknownSetting(token.to_string()), loc)),
+        }
+    }
+    Ok(instrument)
+}
+
+fn range_pitch(token: &Token) -> CompileFinalResult<Pitch> {
+    match pitch(token, Key::default()) {
+        Ok(Some(pitch)) => Ok(pitch),
+        Ok(None) => Err((ExpectedPitch, token.location())),
+        Err(err) => Err((err, token.location())),
+    }
+}
+
+fn clef(token: &Token) -> CompileFinalResult<Clef> {
+    match token {
+        Token::Identifier("treble", _) => Ok(Clef::Treble),
+        Token::Identifier("bass", _) => Ok(Clef::Bass),
+        Token::Identifier("alto", _) => Ok(Clef::Alto),
+        Token::Identifier("tenor", _) => Ok(Clef::Tenor),
+        Token::Identifier("percussion", _) => Ok(Clef::Percussion),
+        token => Err((UnknownClef(token.to_string()), token.location())),
+    }
+}
+
+fn lower_directive(call: &CallNode) -> CompileFinalResult<Directive> {
+    let loc = call.identifier.location();
+    match call.identifier {
+        Token::Identifier("bpm", _) => match call.arguments.as_slice() {
+            [ArgumentNode { argument: Token::Number(bpm @ 1..=1000, _) }] => Ok(Directive::Tempo(*bpm)),
+            [ArgumentNode { argument: Token::Number(bpm, loc) }] => Err((BadTempo(*bpm), *loc)),
+            _ => Err((ExpectedNumber, loc)),
+        },
+        // `accel(140, 4)` reaches 140 bpm over the next four measures.
+        Token::Identifier(name @ ("accel" | "rit"), _) => match call.arguments.as_slice() {
+            [ArgumentNode { argument: Token::Number(target @ 1..=1000, _) }, ArgumentNode { argument: Token::Number(measures @ 1.., _) }] => {
+                Ok(Directive::Ramp { target: *target, measures: *measures, faster: name == "accel" })
+            }
+            [ArgumentNode { argument: Token::Number(target, loc) }, _] if !(1..=1000).contains(target) => Err((BadTempo(*target), *loc)),
+            [_, _, extra, ..] => Err((UnexpectedArgument(extra.argument.to_string()), extra.argument.location())),
+            [_, argument] => Err((ExpectedNumber, argument.argument.location())),
+            _ => Err((ExpectedNumber, loc)),
+        },
+        token => Err((UnknownDirective(token.to_string()), loc)),
+    }
+}
+
+// `velocity(ff, 110)` changes the velocity a dynamic plays at.
+fn lower_velocity(call: &CallNode) -> CompileFinalResult<(Dynamic, u8)> {
+    match call.arguments.as_slice() {
+        [ArgumentNode { argument: dynamic }, ArgumentNode { argument: velocity }] => {
+            let dynamic = match dynamic {
+                Token::Identifier(name, _) => Dynamic::parse(name),
+                _ => None,
+            }
+            .ok_or_else(|| (UnknownDynamic(dynamic.to_string()), dynamic.location()))?;
+            match velocity {
+                Token::Number(velocity @ 1..=127, _) => Ok((dynamic, *velocity as u8)),
+                Token::Number(velocity, loc) => Err((BadVelocity(*velocity), *loc)),
+                token => Err((ExpectedNumber, token.location())),
+            }
+        }
+        [_, _, extra, ..] => Err((UnexpectedArgument(extra.argument.to_string()), extra.argument.location())),
+        _ => Err((ExpectedNumber, call.identifier.location())),
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Settings {
+    voicing: Voicing,
+    key: Key,
+    signature: TimeSignature,
+}
+
+fn lower_voicing(call: &CallNode) -> CompileFinalResult<Voicing> {
+    let (style, octave) = match call.arguments.as_slice() {
+        [style] => (&style.argument, None),
+        [style, octave] => (&style.argument, Some(&octave.argument)),
+        [_, _, extra, ..] => return Err((UnexpectedArgument(extra.argument.to_string()), extra.argument.location())),
+        [] => return Err((UnknownVoicing(String::new()), call.identifier.location())),
+    };
+    let style = match style {
+        Token::Identifier("close", _) => VoicingStyle::Close,
+        Token::Identifier("drop2", _) => VoicingStyle::Drop2,
+        Token::Identifier("root", _) => VoicingStyle::Root,
+        token => return Err((UnknownVoicing(token.to_string()), token.location())),
+    };
+    let octave = match octave {
+        None => Note::DEFAULT_OCTAVE,
+        Some(Token::Number(octave @ 0..=9, _)) => *octave as u8,
+        Some(token) => return Err((ExpectedNumber, token.location())),
+    };
+    Ok(Voicing { style, octave })
+}
+
+fn lower_key(call: &CallNode) -> CompileFinalResult<Key> {
+    let (tonic, mode) = match call.arguments.as_slice() {
+        [tonic] => (&tonic.argument, None),
+        [tonic, mode] => (&tonic.argument, Some(&mode.argument)),
+        [_, _, extra, ..] => return Err((UnexpectedArgument(extra.argument.to_string()), extra.argument.location())),
+        [] => return Err((ExpectedPitch, call.identifier.location())),
+    };
+    let note = match tonic {
+        Token::Note(note, _, _, _) => *note,
+        Token::Literal(name, _) => Note::parse(name).map(|(note, _, _)| note).ok_or_else(|| (UnknownPitch(name.to_string()), tonic.location()))?,
+        token => return Err((UnknownPitch(token.to_string()), token.location())),
+    };
+    let mode = match mode {
+        None | Some(Token::Identifier("major", _)) => Mode::Major,
+        Some(Token::Identifier("minor", _)) => Mode::Minor,
+        Some(token) => return Err((UnknownMode(token.to_string()), token.location())),
+    };
+    Key::new(note, mode).ok_or_else(|| (UnknownKey(Key { tonic: note, mode }.to_string()), tonic.location()))
+}
+
+fn lower_block<'src>(block: &BlockNode<'src>, docs: Vec<&'src str>, directives: Vec<(Directive, Location)>, settings: Settings, location: Location) -> CompileFinalResult<Measure<'src>> {
+    let mut voices: Vec<Voice> = vec![];
+    // Once a measure is split into voices, every note must belong to one.
+    if block.statements.iter().any(|statement| statement.voice.is_some()) {
+        for statement in &block.statements {
+            let Some(node) = &statement.voice else {
+                let location = statement.call.as_ref().map_or(location, |call| call.call.identifier.location());
+                return Err((NoteOutsideVoice, statement.tuplet.as_ref().map_or(location, |tuplet| tuplet.head.location())));
+            };
+            let number = match node.number {
+                Token::Number(number @ 1.., _) => number,
+                token => return Err((BadVoice(token.to_string()), token.location())),
+            };
+            if voices.iter().any(|voice| voice.number == number) {
+                return Err((DuplicateVoice(number), node.number.location()));
+            }
+            let mut events = vec![];
+            lower_statements(&node.block.statements, Fraction::new(1, 1), settings, &mut events)?;
+            voices.push(Voice { number, events, location: node.keyword.location() });
+        }
+    } else {
+        let mut events = vec![];
+        lower_statements(&block.statements, Fraction::new(1, 1), settings, &mut events)?;
+        voices.push(Voice { number: 1, events, location });
+    }
+    Ok(Measure { docs, key: settings.key, signature: settings.signature, directives, voices, location })
+}
+
+// Tuplets scale every duration inside them; nested tuplets multiply.
+fn lower_statements<'src>(statements: &[BlockStatementNode<'src>], scale: Fraction, settings: Settings, events: &mut Vec<Event<'src>>) -> CompileFinalResult<()> {
+    for statement in statements {
+        if let Some(call) = &statement.call {
+            events.push(lower_event(call, scale, settings)?);
+        }
+        if let Some(tuplet) = &statement.tuplet {
+            let (actual, normal) = tuplet_ratio(&tuplet.head).map_err(|err| (err, tuplet.head.location()))?;
+            lower_statements(&tuplet.block.statements, scale * Fraction::new(normal as u64, actual as u64), settings, events)?;
+        }
+        if let Some(voice) = &statement.voice {
+            return Err((NestedVoice, voice.keyword.location()));
+        }
+    }
+    Ok(())
+}
+
+fn lower_event<'src>(node: &CallWithNode<'src>, scale: Fraction, settings: Settings) -> CompileFinalResult<Event<'src>> {
+    let location = node.call.identifier.location();
+    let value = duration_value(&node.call.identifier).map_err(|err| (err, location))?;
+    let mut duration = Duration { value, dots: 0, scale };
+    // Several pitches sound together as a chord; `rest` and chord symbols must stand alone.
+    let mut pitches: Vec<Pitch> = vec![];
+    let mut symbol = false;
+    for argument in &node.call.arguments {
+        let token = &argument.argument;
+        if let Some(chord) = chord_symbol(token).map_err(|err| (err, token.location()))? {
+            if node.call.arguments.len() > 1 {
+                return Err((SymbolInChord(token.to_string()), token.location()));
+            }
+            pitches = chord.voice(settings.voicing);
+            symbol = true;
+            continue;
+        }
+        match pitch(token, settings.key).map_err(|err| (err, token.location()))? {
+            Some(pitch) if pitches.iter().any(|other| other.midi() == pitch.midi()) => {
+                return Err((DuplicatePitch(pitch.to_string()), token.location()))
+            }
+            Some(pitch) => pitches.push(pitch),
+            None if node.call.arguments.len() > 1 => return Err((RestInChord, token.location())),
+            None => {}
+        }
+    }
+    if node.call.arguments.is_empty() {
+        return Err((ExpectedPitch, location));
+    }
+    let mut modifiers = vec![];
+    let mut articulations = vec![];
+    let mut tie = false;
+    let mut dynamic = None;
+    let mut hairpin = None;
+    for with in &node.with {
+        let modifier = match (&with.identifier, &with.call) {
+            (Some(identifier), _) => Modifier { name: name(identifier), arguments: vec![], location: identifier.location() },
+            (None, Some(call)) => Modifier {
+                name: name(&call.identifier),
+                arguments: call.arguments.iter().map(|argument| argument.argument).collect(),
+                location: call.identifier.location(),
+            },
+            (None, None) => continue,
+        };
+        articulations::check(&modifier)?;
+        let top = pitches.iter().max_by_key(|pitch| pitch.midi()).copied();
+        let articulation = match modifier.name {
+            "dot" | "doubledot" => {
+                duration.dots += if modifier.name == "dot" { 1 } else { 2 };
+                if duration.dots > 2 {
+                    return Err((TooManyDots, modifier.location));
+                }
+                continue;
+            }
+            "tie" => {
+                tie = true;
+                continue;
+            }
+            "cresc" | "dim" | "decresc" => {
+                let found = if modifier.name == "cresc" { Hairpin::Crescendo } else { Hairpin::Diminuendo };
+                if hairpin.replace(found).is_some() {
+                    return Err((NestedHairpin, modifier.location));
+                }
+                continue;
+            }
+            "grace" if top.is_none() => return Err((ArticulationOnRest(modifier.name.to_string()), modifier.location)),
+            "grace" => {
+                for argument in &modifier.arguments {
+                    let grace = pitch(argument, settings.key).map_err(|err| (err, argument.location()))?;
+                    articulations.push(Articulation::Grace(grace.ok_or((ExpectedPitch, argument.location()))?));
+                }
+                continue;
+            }
+            "staccato" => Articulation::Staccato,
+            "staccatissimo" => Articulation::Staccatissimo,
+            "tenuto" => Articulation::Tenuto,
+            "accent" => Articulation::Accent,
+            "marcato" => Articulation::Marcato,
+            "tremolo" => Articulation::Tremolo,
+            // Trills alternate with the scale step above by default, mordents with the step below.
+            "trill" | "mordent" => {
+                let steps = if modifier.name == "trill" { 1 } else { -1 };
+                let neighbour = match modifier.arguments.first() {
+                    Some(argument) => pitch(argument, settings.key).map_err(|err| (err, argument.location()))?,
+                    None => top.map(|top| top.step(settings.key, steps)),
+                };
+                let neighbour = neighbour.ok_or((ExpectedPitch, modifier.location))?;
+                if modifier.name == "trill" { Articulation::Trill(neighbour) } else { Articulation::Mordent(neighbour) }
+            }
+            name => match Dynamic::parse(name) {
+                Some(found) => {
+                    if dynamic.replace(found).is_some() {
+                        return Err((DuplicateDynamic, modifier.location));
+                    }
+                    continue;
+                }
+                None => {
+                    modifiers.push(modifier);
+                    continue;
+                }
+            },
+        };
+        if top.is_none() {
+            return Err((ArticulationOnRest(modifier.name.to_string()), modifier.location));
+        }
+        articulations.push(articulation);
+    }
+    Ok(Event { pitches, symbol, duration, modifiers, tie, legato: false, articulations, dynamic, hairpin, velocity: 0, location })
+}
+
+fn literal_argument<'src>(call: &CallNode<'src>) -> CompileFinalResult<&'src str> {
+    match call.arguments.as_slice() {
+        [ArgumentNode { argument: Token::Literal(value, _) }] => Ok(*value),
+        [argument, ..] => Err((ExpectedLiteral, argument.argument.location())),
+        [] => Err((ExpectedLiteral, call.identifier.location())),
+    }
+}
+
+fn name<'src>(token: &Token<'src>) -> &'src str {
+    match token {
+        Token::Identifier(name, _) | Token::Literal(name, _) => name,
+        _ => "",
+    }
+}
+
+fn lower_signature(token: &Token) -> CompileFinalResult<TimeSignature> {
+    match token {
+        Token::Signature(top, bottom, loc) => time_signature(*top, *bottom).map_err(|err| (err, *loc)),
+        _ => unreachable!("the parser only accepts signature tokens here"),
+    }
+}
+
+fn time_signature(top: u32, bottom: u32) -> CompileResult<TimeSignature> {
+    if top == 0 || top > 255 || !bottom.is_power_of_two() || bottom > 64 {
+        return Err(BadSignature(top, bottom));
+    }
+    Ok(TimeSignature { top, bottom })
+}
+
+fn duration_value(token: &Token) -> CompileResult<u32> {
+    match token {
+        Token::Identifier("whole", _) | Token::Number(1, _) => Ok(1),
+        Token::Identifier("half", _) | Token::Number(2, _) => Ok(2),
+        Token::Identifier("quarter", _) | Token::Number(4, _) => Ok(4),
+        Token::Identifier("eighth", _) | Token::Number(8, _) => Ok(8),
+        Token::Identifier("sixteenth", _) | Token::Number(16, _) => Ok(16),
+        Token::Identifier("thirtysecond", _) | Token::Number(32, _) => Ok(32),
+        Token::Identifier("sixtyfourth", _) | Token::Number(64, _) => Ok(64),
+        token => Err(UnknownDuration(token.to_string())),
+    }
+}
+
+// Returns how many notes are played in the time of how many, so a triplet is 3:2.
+fn tuplet_ratio(token: &Token) -> CompileResult<(u32, u32)> {
+    match token {
+        Token::Identifier("duplet", _) => Ok((2, 3)),
+        Token::Identifier("triplet", _) => Ok((3, 2)),
+        Token::Identifier("quadruplet", _) => Ok((4, 3)),
+        Token::Identifier("quintuplet", _) => Ok((5, 4)),
+        Token::Identifier("sextuplet", _) => Ok((6, 4)),
+        Token::Identifier("septuplet", _) => Ok((7, 4)),
+        Token::Ratio(actual @ 1.., normal @ 1.., _) => Ok((*actual, *normal)),
+        Token::Ratio(actual, normal, _) => Err(BadTuplet(*actual, *normal)),
+        token => Err(UnknownTuplet(token.to_string())),
+    }
+}
+
+// Symbols such as `G7` also read as notes, so chords are only taken from `chord(G7)`. Symbols
+// that could never be notes are caught rather than read as unknown pitches.
+fn chord_symbol(token: &Token) -> CompileResult<Option<ChordSymbol>> {
+    match token {
+        Token::Chord(symbol, _) => ChordSymbol::parse(symbol).map(Some).ok_or_else(|| UnknownChord(symbol.to_string())),
+        Token::Identifier(symbol, _) | Token::Literal(symbol, _) if Note::parse(symbol).is_none() && ChordSymbol::parse(symbol).is_some() => {
+            Err(UnmarkedChord(symbol.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Notes written without an accidental take the one the key gives their letter.
+fn pitch(token: &Token, key: Key) -> CompileResult<Option<Pitch>> {
+    let (note, octave, explicit) = match token {
+        Token::Note(note, octave, explicit, _) => (*note, *octave, *explicit),
+        Token::Literal("rest", _) | Token::Identifier("rest", _) => return Ok(None),
+        Token::Literal(name, _) | Token::Identifier(name, _) if is_misspelled_note(name) => return Err(InvalidNote(name.to_string())),
+        Token::Literal(name, _) => Note::parse(name).ok_or_else(|| UnknownPitch(name.to_string()))?,
+        token => return Err(UnknownPitch(token.to_string())),
+    };
+    let note = if explicit { note } else { key.apply(note) };
+    let pitch = Pitch { note, octave };
+    if !(0..=127).contains(&pitch.midi()) {
+        return Err(UnknownPitch(pitch.to_string()));
+    }
+    Ok(Some(pitch))
+}
+
+// Words shaped like a pitch but with a letter outside A to G, such as `H4`.
+fn is_misspelled_note(value: &str) -> bool {
+    let mut chars = value.chars();
+    let letter = chars.next().filter(|letter| letter.is_ascii_uppercase() && !matches!(letter, 'A'..='G'));
+    let octave = chars.as_str().trim_start_matches(['#', 'b', 'n', '♯', '♭', '♮', '𝄪', '𝄫']);
+    letter.is_some() && !octave.is_empty() && octave.chars().all(|c| c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The MIDI keys of each event in the first measure of the only staff.
+    fn keys(block: &str) -> CompileFinalResult<Vec<Vec<i32>>> {
+        let source = format!("staff s is treble() in [4/4] {{ measure {{ {} }} }}", block);
+        let score = compile(&source)?;
+        let events = score.staves[0].measures[0].events();
+        Ok(events.map(|event| event.pitches.iter().map(Pitch::midi).collect()).collect())
+    }
+
+    #[test]
+    fn chords_are_marked() {
+        assert_eq!(keys("4(G7) 4(C9) 4(\"C\") 4(\"C4\")").unwrap(), [vec![103], vec![120], vec![60], vec![60]]);
+        assert_eq!(keys("4(chord(G7)) 4(chord(C6/9))").unwrap(), [vec![67, 71, 74, 77], vec![60, 62, 64, 67, 69]]);
+        assert_eq!(keys("1(chord(C9))").unwrap(), [vec![60, 62, 64, 67, 70]]);
+    }
+
+    #[test]
+    fn unmarked_and_unknown_chords() {
+        assert!(matches!(keys("1(Cmaj7)"), Err((UnmarkedChord(symbol), _)) if symbol == "Cmaj7"));
+        assert!(matches!(keys("1(\"Dm7\")"), Err((UnmarkedChord(symbol), _)) if symbol == "Dm7"));
+        assert!(matches!(keys("1(chord(Cwhat))"), Err((UnknownChord(symbol), _)) if symbol == "Cwhat"));
+        assert!(matches!(keys("1(chord(G7), B4)"), Err((SymbolInChord(_), _))));
+    }
+
+    fn linked(measures: &str) -> CompileFinalResult<Vec<(bool, bool)>> {
+        let source = format!("staff s is treble() in [2/4] {{ {} }}", measures);
+        let score = compile(&source)?;
+        Ok(score.staves[0].measures.iter().flat_map(Measure::events).map(|event| (event.tie, event.legato)).collect())
+    }
+
+    #[test]
+    fn ties_cross_barlines() {
+        assert_eq!(linked("measure { 4(C) 4(E) with tie } measure { 4(E) 4(D) }").unwrap(), [(false, false), (true, false), (false, false), (false, false)]);
+        assert!(linked("measure { 4(C, E) with tie 4(C, E, G) }").is_ok());
+        assert!(linked("measure { 4(Fb) with tie 4(E) }").is_ok());
+        assert!(matches!(linked("measure { 4(C, E) with tie 4(C, G) }"), Err((UnmatchedTie, _))));
+        assert!(matches!(linked("measure { 4(C) 4(E) with tie }"), Err((UnmatchedTie, _))));
+        assert!(matches!(linked("measure { 4(rest) with tie 4(C) }"), Err((UnmatchedTie, _))));
+    }
+
+    #[test]
+    fn slurs() {
+        let legato = linked("measure { 4(C) with slur 4(D) } measure { 4(E) with endslur 4(F) }").unwrap();
+        assert_eq!(legato.iter().map(|(_, legato)| *legato).collect::<Vec<_>>(), [true, true, false, false]);
+        assert!(matches!(linked("measure { 4(C) with slur 4(D) with slur } measure { 2(E) with endslur }"), Err((NestedSlur, _))));
+        assert!(matches!(linked("measure { 4(C) 4(D) with endslur }"), Err((UnmatchedSlur, _))));
+        assert!(matches!(linked("measure { 4(C) with slur 4(D) }"), Err((UnclosedSlur, Location { col: 52, .. }))));
+        // Each voice slurs on its own.
+        assert!(linked("measure { voice 1 { 4(C5) with slur 4(D5) with endslur } voice 2 { 2(C4) } }").is_ok());
+    }
+
+    #[test]
+    fn misspelled_notes() {
+        assert!(matches!(keys("1(H4)"), Err((InvalidNote(name), _)) if name == "H4"));
+        assert!(matches!(keys("1(\"Hb3\")"), Err((InvalidNote(name), _)) if name == "Hb3"));
+        assert!(matches!(keys("1(V)"), Err((UnknownPitch(name), _)) if name == "V"));
+    }
+}
@@ -1,18 +1,24 @@
-#![feature(bench_black_box)]
-use lexer::Lexer;
-use parser::Parser;
+use std::process::ExitCode;
 
+mod articulations;
+mod check;
+mod chords;
+mod cli;
+mod diagnostics;
+mod dynamics;
 mod errors;
+mod fmt;
 mod lexer;
+mod lower;
+mod midi;
+mod modules;
 mod nodes;
 mod parser;
+mod score;
+mod tempo;
 mod tokens;
+mod unroll;
 
-fn main() {
-    let source = include_str!("../example/test.tn");
-    let lexer = Lexer::new(source);
-    let parser = Parser::new(lexer);
-    let node = parser.parse();
-    let a = node.unwrap();
-    dbg!(&a);
+fn main() -> ExitCode {
+    cli::run(std::env::args().skip(1))
 }
@@ -0,0 +1,278 @@
+use midly::{
+    num::{u15, u24, u28, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind,
+};
+
+use crate::{score::*, unroll::unroll};
+
+const TICKS_PER_QUARTER: u32 = 480;
+const LEGATO_OVERLAP: u32 = 20;
+// Ornaments and grace notes move in thirty-second notes.
+const ORNAMENT_TICKS: u32 = TICKS_PER_QUARTER / 8;
+
+// An event with the ticks it starts and ends on.
+type Timed<'a, 'src> = (u32, u32, &'a Event<'src>);
+
+struct Track<'src> {
+    events: Vec<(u32, TrackEventKind<'src>)>,
+}
+
+impl<'src> Track<'src> {
+    fn new() -> Self {
+        Self { events: vec![] }
+    }
+    fn meta(&mut self, tick: u32, message: MetaMessage<'src>) {
+        self.events.push((tick, TrackEventKind::Meta(message)));
+    }
+    fn midi(&mut self, tick: u32, channel: u8, message: MidiMessage) {
+        self.events.push((tick, TrackEventKind::Midi { channel: u4::new(channel), message }));
+    }
+    fn finish(mut self) -> Vec<TrackEvent<'src>> {
+        // Note-offs sort before note-ons on the same tick so repeated pitches retrigger.
+        self.events.sort_by_key(|(tick, kind)| {
+            let order = match kind {
+                TrackEventKind::Meta(_) => 0,
+                TrackEventKind::Midi { message: MidiMessage::NoteOff { .. }, .. } => 1,
+                _ => 2,
+            };
+            (*tick, order)
+        });
+        let mut last = 0;
+        let mut track = vec![];
+        for (tick, kind) in self.events {
+            track.push(TrackEvent { delta: u28::new(tick - last), kind });
+            last = tick;
+        }
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        track
+    }
+}
+
+pub fn export(score: &Score) -> Vec<u8> {
+    let mut conductor = Track::new();
+    if let Some(title) = score.title {
+        conductor.meta(0, MetaMessage::TrackName(title.as_bytes()));
+    }
+    for text in [score.composer, score.description].into_iter().flatten() {
+        conductor.meta(0, MetaMessage::Text(text.as_bytes()));
+    }
+    for (position, micros) in &score.tempo.changes {
+        conductor.meta(ticks(*position), MetaMessage::Tempo(u24::new(*micros)));
+    }
+    let mut tracks = vec![];
+    let mut signatures = vec![];
+    // The checks have already rejected scores with a staff left without a channel.
+    for (staff, channel) in score.staves.iter().zip(score.channels()) {
+        let staff_channel = channel.unwrap_or(0);
+        let mut track = Track::new();
+        track.meta(0, MetaMessage::TrackName(staff.name.as_bytes()));
+        for doc in &staff.docs {
+            track.meta(0, MetaMessage::Text(doc.as_bytes()));
+        }
+        if let Some(instrument) = &staff.instrument {
+            track.meta(0, MetaMessage::InstrumentName(instrument.name.as_bytes()));
+            track.midi(0, staff_channel, MidiMessage::ProgramChange { program: u7::new(instrument.program) });
+        }
+        // Positions stay exact until they become ticks, so tuplets don't drift from the beat.
+        let mut position = Fraction::zero();
+        // Voices share the staff's track and channel but tie and slur independently.
+        let mut timelines: Vec<(u32, Vec<Timed>)> = staff.voices().into_iter().map(|number| (number, vec![])).collect();
+        let mut key = None;
+        let mut signature = None;
+        for measure in unroll(staff) {
+            let tick = ticks(position);
+            if signature != Some(measure.signature) {
+                signatures.push((tick, measure.signature));
+                signature = Some(measure.signature);
+            }
+            // Doc comments on a measure become markers, such as rehearsal letters.
+            for doc in &measure.docs {
+                track.meta(tick, MetaMessage::Marker(doc.as_bytes()));
+            }
+            if key != Some(measure.key) {
+                track.meta(tick, MetaMessage::KeySignature(measure.key.fifths(), measure.key.mode == Mode::Minor));
+                key = Some(measure.key);
+            }
+            for voice in &measure.voices {
+                let Some((_, timeline)) = timelines.iter_mut().find(|(number, _)| *number == voice.number) else { continue };
+                let mut start = position;
+                for event in &voice.events {
+                    let end = start + event.duration.length();
+                    timeline.push((ticks(start), ticks(end), event));
+                    start = end;
+                }
+            }
+            position = position + measure.length();
+        }
+        for (_, timeline) in &timelines {
+            export_events(&mut track, staff_channel, timeline);
+        }
+        tracks.push(track.finish());
+    }
+    // Staffs usually agree on their meter changes, so the conductor only needs each one once.
+    signatures.sort_by_key(|(tick, signature)| (*tick, signature.top, signature.bottom));
+    signatures.dedup();
+    for (tick, signature) in signatures {
+        conductor.meta(tick, time_signature(signature));
+    }
+    tracks.insert(0, conductor.finish());
+
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(TICKS_PER_QUARTER as u16)),
+    ));
+    smf.tracks = tracks;
+    let mut bytes = vec![];
+    smf.write_std(&mut bytes).expect("writing to a Vec cannot fail");
+    bytes
+}
+
+fn export_events(track: &mut Track, channel: u8, timeline: &[Timed]) {
+    // Keys tied over from the previous event keep sounding instead of being struck again.
+    let mut held: Vec<u8> = vec![];
+    for (index, &(tick, end, event)) in timeline.iter().enumerate() {
+        let next = timeline.get(index + 1).map(|(_, _, next)| *next);
+        let keys: Vec<u8> = event.pitches.iter().map(|pitch| pitch.midi() as u8).collect();
+        let accent = event.articulations.iter().map(Articulation::accent).max().unwrap_or(0);
+        let velocity = event.velocity.saturating_add(accent).min(127);
+        // A jump back may land on a note other than the one a tie was written to.
+        for key in held.iter().filter(|key| !keys.contains(key)) {
+            track.midi(tick, channel, MidiMessage::NoteOff { key: u7::new(*key), vel: u7::new(0) });
+        }
+        let mut start = tick;
+        if !keys.iter().any(|key| held.contains(key)) {
+            start = ornament(track, channel, event, &keys, start, end, velocity);
+        }
+        let gate = event.articulations.iter().find_map(Articulation::gate);
+        let sounding = match gate {
+            Some(gate) if !event.tie => start + ((end - start) as u64 * gate.num() / gate.den()) as u32,
+            _ => end,
+        };
+        let mut tied = vec![];
+        for key in &keys {
+            if !held.contains(key) {
+                track.midi(start, channel, MidiMessage::NoteOn { key: u7::new(*key), vel: u7::new(velocity) });
+            }
+            if event.tie {
+                tied.push(*key);
+                continue;
+            }
+            // Slurred notes overlap the next one slightly, unless it restrikes the same key.
+            let restruck = next.is_some_and(|next| next.pitches.iter().any(|other| other.midi() as u8 == *key));
+            let overlap = if event.legato && gate.is_none() && !restruck { LEGATO_OVERLAP } else { 0 };
+            track.midi(sounding + overlap, channel, MidiMessage::NoteOff { key: u7::new(*key), vel: u7::new(0) });
+        }
+        held = tied;
+    }
+}
+
+// Plays grace notes and ornaments in short strikes from the start of a note, and returns where
+// the final strike of the note itself begins.
+fn ornament(track: &mut Track, channel: u8, event: &Event, keys: &[u8], mut start: u32, end: u32, velocity: u8) -> u32 {
+    let strike = |track: &mut Track, start: u32, keys: &[u8]| {
+        for key in keys {
+            track.midi(start, channel, MidiMessage::NoteOn { key: u7::new(*key), vel: u7::new(velocity) });
+            track.midi(start + ORNAMENT_TICKS, channel, MidiMessage::NoteOff { key: u7::new(*key), vel: u7::new(0) });
+        }
+    };
+    // Grace notes take their time from the note they lead into, but never more than half of it.
+    let graces: Vec<u8> = event.articulations.iter().filter_map(|articulation| match articulation {
+        Articulation::Grace(pitch) => Some(pitch.midi() as u8),
+        _ => None,
+    }).collect();
+    for grace in graces.iter().take(((end - start) / 2 / ORNAMENT_TICKS) as usize) {
+        strike(track, start, &[*grace]);
+        start += ORNAMENT_TICKS;
+    }
+    for articulation in &event.articulations {
+        match articulation {
+            Articulation::Tremolo => {
+                while start + 2 * ORNAMENT_TICKS <= end {
+                    strike(track, start, keys);
+                    start += ORNAMENT_TICKS;
+                }
+            }
+            Articulation::Trill(neighbour) => {
+                while start + 3 * ORNAMENT_TICKS <= end {
+                    strike(track, start, keys);
+                    strike(track, start + ORNAMENT_TICKS, &[neighbour.midi() as u8]);
+                    start += 2 * ORNAMENT_TICKS;
+                }
+            }
+            Articulation::Mordent(neighbour) if start + 3 * ORNAMENT_TICKS <= end => {
+                strike(track, start, keys);
+                strike(track, start + ORNAMENT_TICKS, &[neighbour.midi() as u8]);
+                start += 2 * ORNAMENT_TICKS;
+            }
+            _ => {}
+        }
+    }
+    start
+}
+
+fn ticks(length: Fraction) -> u32 {
+    (length.num() * TICKS_PER_QUARTER as u64 * 4 / length.den()) as u32
+}
+
+// The metronome clicks once per beat, counted in MIDI clocks of 24 per quarter note.
+fn time_signature(signature: TimeSignature) -> MetaMessage<'static> {
+    let click = signature.beats().first().map_or(24, |beat| ticks(*beat) * 24 / TICKS_PER_QUARTER);
+    MetaMessage::TimeSignature(signature.top as u8, signature.bottom.trailing_zeros() as u8, click as u8, 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{dynamics, lower::compile};
+
+    // The note-ons and note-offs of the first staff, as (tick, on, key).
+    fn notes(measures: &str) -> Vec<(u32, bool, u8)> {
+        let source = format!("staff s is treble() in [2/4] {{ {} }}", measures);
+        let mut score = compile(&source).unwrap();
+        dynamics::apply(&mut score).unwrap();
+        let bytes = export(&score);
+        let smf = Smf::parse(&bytes).unwrap();
+        let mut tick = 0;
+        let mut notes = vec![];
+        for event in &smf.tracks[1] {
+            tick += event.delta.as_int();
+            match event.kind {
+                TrackEventKind::Midi { message: MidiMessage::NoteOn { key, .. }, .. } => notes.push((tick, true, key.as_int())),
+                TrackEventKind::Midi { message: MidiMessage::NoteOff { key, .. }, .. } => notes.push((tick, false, key.as_int())),
+                _ => {}
+            }
+        }
+        notes
+    }
+
+    #[test]
+    fn ties_hold_keys_across_barlines() {
+        assert_eq!(
+            notes("measure { 4(C4) 4(E4) with tie } measure { 4(E4) 4(E4) }"),
+            [(0, true, 60), (480, false, 60), (480, true, 64), (1440, false, 64), (1440, true, 64), (1920, false, 64)]
+        );
+        // Only the tied keys of a chord are held; the new one is struck.
+        assert_eq!(
+            notes("measure { 2(C4) with tie } measure { 2(C4, E4) }"),
+            [(0, true, 60), (960, true, 64), (1920, false, 60), (1920, false, 64)]
+        );
+    }
+
+    #[test]
+    fn tied_chains_and_repeated_keys() {
+        assert_eq!(notes("measure { 4(G4) with tie 4(G4) with tie } measure { 2(G4) }"), [(0, true, 67), (1920, false, 67)]);
+        // A key struck again right away is released first.
+        assert_eq!(notes("measure { 4(G4) 4(G4) }"), [(0, true, 67), (480, false, 67), (480, true, 67), (960, false, 67)]);
+    }
+
+    #[test]
+    fn repeats_release_held_keys() {
+        assert_eq!(
+            notes("repeat { measure { 2(C4) } measure { 2(D4) with tie } } measure { 2(D4) }"),
+            [(0, true, 60), (960, false, 60), (960, true, 62), (1920, false, 62), (1920, true, 60), (2880, false, 60), (2880, true, 62), (4800, false, 62)]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnostics::Diagnostic,
+    errors::{ModuleError::*, ModuleFinalError, ModuleResult},
+    lexer::Lexer,
+    nodes::*,
+    parser::Parser,
+    tokens::{Location, Token},
+};
+
+const STD_PREFIX: &str = "std/";
+const STD_MODULES: &[(&str, &str)] = &[("std/instruments", include_str!("std/instruments.tn"))];
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub source: String,
+}
+
+#[derive(Debug)]
+pub struct Module<'src> {
+    pub program: ProgramNode<'src>,
+    pub imports: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct Scope<'a, 'src> {
+    pub instruments: HashMap<&'src str, &'a InstrumentDeclarationNode<'src>>,
+    pub staffs: Vec<(&'a StaffDeclarationNode<'src>, Scope<'a, 'src>)>,
+}
+
+pub struct ModuleLoader {
+    search_paths: Vec<PathBuf>,
+    files: Vec<SourceFile>,
+    keys: Vec<String>,
+    imports: Vec<Vec<usize>>,
+}
+
+enum Located {
+    Embedded(&'static str, &'static str),
+    File(PathBuf),
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, path: Option<PathBuf>, source: String) -> Self {
+        Self { name: name.into(), path, source }
+    }
+}
+
+impl ModuleLoader {
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        Self { search_paths, files: vec![], keys: vec![], imports: vec![] }
+    }
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+    // Reads the root file and everything it imports, transitively.
+    pub fn load(&mut self, root: SourceFile) -> Vec<Diagnostic> {
+        let key = match &root.path {
+            Some(path) => path.canonicalize().unwrap_or_else(|_| path.clone()).display().to_string(),
+            None => root.name.clone(),
+        };
+        let root = self.add(root, key);
+        let mut diagnostics = vec![];
+        self.visit(root, &mut vec![], &mut diagnostics);
+        diagnostics
+    }
+    // Parses every loaded file. Parse errors were already reported by `load`.
+    pub fn parse(&self) -> Vec<Module<'_>> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(file, source)| {
+                let (program, _) = Parser::new(Lexer::with_file(&source.source, file)).parse();
+                Module { program, imports: self.imports[file].clone() }
+            })
+            .collect()
+    }
+    fn add(&mut self, file: SourceFile, key: String) -> usize {
+        self.files.push(file);
+        self.keys.push(key);
+        self.imports.push(vec![]);
+        self.files.len() - 1
+    }
+    fn visit(&mut self, file: usize, stack: &mut Vec<usize>, diagnostics: &mut Vec<Diagnostic>) {
+        stack.push(file);
+        let requests: Vec<(String, Location)> = {
+            let parser = Parser::new(Lexer::with_file(&self.files[file].source, file));
+            let (program, errors) = parser.parse();
+            diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+            program.imports.iter().map(|import| (literal(&import.source).to_string(), import.source.location())).collect()
+        };
+        for (request, location) in requests {
+            match self.request(file, &request, stack, diagnostics) {
+                Ok(target) => self.imports[file].push(target),
+                Err(err) => diagnostics.push((err, location).into()),
+            }
+        }
+        stack.pop();
+    }
+    fn request(&mut self, from: usize, request: &str, stack: &mut Vec<usize>, diagnostics: &mut Vec<Diagnostic>) -> ModuleResult<usize> {
+        let located = self.locate(from, request)?;
+        let key = match &located {
+            Located::Embedded(name, _) => name.to_string(),
+            Located::File(path) => path.canonicalize().unwrap_or_else(|_| path.clone()).display().to_string(),
+        };
+        if let Some(target) = self.keys.iter().position(|loaded| *loaded == key) {
+            return self.loaded(target, stack);
+        }
+        let file = match located {
+            Located::Embedded(name, source) => SourceFile::new(name, None, source.to_string()),
+            Located::File(path) => {
+                let source = std::fs::read_to_string(&path).map_err(|err| Unreadable(request.to_string(), err.to_string()))?;
+                SourceFile::new(path.display().to_string(), Some(path), source)
+            }
+        };
+        let target = self.add(file, key);
+        self.visit(target, stack, diagnostics);
+        Ok(target)
+    }
+    fn loaded(&self, target: usize, stack: &[usize]) -> ModuleResult<usize> {
+        match stack.iter().position(|file| *file == target) {
+            Some(start) => {
+                let chain = stack[start..].iter().chain([&target]).map(|file| self.files[*file].name.clone()).collect();
+                Err(Cyclic(chain))
+            }
+            None => Ok(target),
+        }
+    }
+    fn locate(&self, from: usize, request: &str) -> ModuleResult<Located> {
+        if request.starts_with(STD_PREFIX) {
+            return match STD_MODULES.iter().find(|(name, _)| *name == request) {
+                Some((name, source)) => Ok(Located::Embedded(name, source)),
+                None => Err(NotFound(request.to_string())),
+            };
+        }
+        let mut relative = PathBuf::from(request);
+        if relative.extension().is_none() {
+            relative.set_extension("tn");
+        }
+        let base = match &self.files[from].path {
+            Some(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+        std::iter::once(&base)
+            .chain(&self.search_paths)
+            .map(|dir| dir.join(&relative))
+            .find(|path| path.is_file())
+            .map(Located::File)
+            .ok_or_else(|| NotFound(request.to_string()))
+    }
+}
+
+// Collects the instruments and staffs visible in a module: its own instruments plus everything it imports.
+pub fn scope<'a, 'src>(modules: &'a [Module<'src>], module: usize) -> Result<Scope<'a, 'src>, Vec<Diagnostic>> {
+    let mut visible = Scope::default();
+    let mut errors: Vec<ModuleFinalError> = vec![];
+    let program = &modules[module].program;
+    let mut staffs: Vec<&str> = vec![];
+    for declaration in &program.declarations {
+        if let Some(staff) = &declaration.staff {
+            let name = literal(&staff.identifier);
+            if staffs.contains(&name) {
+                errors.push((Redefined(name.to_string()), staff.identifier.location()));
+            }
+            staffs.push(name);
+        }
+        if let Some(instrument) = &declaration.instrument {
+            let name = literal(&instrument.identifier);
+            if visible.instruments.insert(name, instrument).is_some() {
+                errors.push((Redefined(name.to_string()), instrument.identifier.location()));
+            }
+        }
+    }
+    for (import, target) in program.imports.iter().zip(&modules[module].imports) {
+        let exports = &modules[*target].program;
+        for item in &import.items {
+            let name = literal(item);
+            let staff = exports.declarations.iter().filter_map(|declaration| declaration.staff.as_ref())
+                .find(|staff| literal(&staff.identifier) == name);
+            let instrument = exports.declarations.iter().filter_map(|declaration| declaration.instrument.as_ref())
+                .find(|instrument| literal(&instrument.identifier) == name);
+            match (staff, instrument) {
+                (Some(staff), _) => {
+                    if staffs.contains(&name) {
+                        errors.push((Redefined(name.to_string()), item.location()));
+                    }
+                    staffs.push(name);
+                    visible.staffs.push((staff, scope(modules, *target)?));
+                }
+                (None, Some(instrument)) => {
+                    if visible.instruments.insert(name, instrument).is_some() {
+                        errors.push((Redefined(name.to_string()), item.location()));
+                    }
+                }
+                (None, None) => errors.push((UnknownExport(name.to_string(), literal(&import.source).to_string()), item.location())),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.into_iter().map(Diagnostic::from).collect());
+    }
+    Ok(visible)
+}
+
+fn literal<'src>(token: &Token<'src>) -> &'src str {
+    match token {
+        Token::Literal(name, _) | Token::Identifier(name, _) => name,
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes the files into a fresh directory and loads the first one as the root.
+    fn load(name: &str, files: &[(&str, &str)], search_paths: &[&str]) -> (PathBuf, ModuleLoader, Vec<Diagnostic>) {
+        let dir = std::env::temp_dir().join(format!("tonal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, source) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        let mut loader = ModuleLoader::new(search_paths.iter().map(|path| dir.join(path)).collect());
+        let root = dir.join(files[0].0);
+        let source = std::fs::read_to_string(&root).unwrap();
+        let diagnostics = loader.load(SourceFile::new(root.display().to_string(), Some(root), source));
+        (dir, loader, diagnostics)
+    }
+
+    #[test]
+    fn cycles_report_the_chain() {
+        let (dir, _, diagnostics) = load(
+            "cycle",
+            &[("a.tn", "import { x } from \"b\""), ("b.tn", "import { y } from \"c\""), ("c.tn", "import { z } from \"a\"")],
+            &[],
+        );
+        let [a, b, c] = ["a.tn", "b.tn", "c.tn"].map(|file| dir.join(file).display().to_string());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, format!("cyclic import: {} -> {} -> {} -> {}", a, b, c, a));
+        assert_eq!((diagnostics[0].location.file, diagnostics[0].location.line, diagnostics[0].location.col), (2, 1, 19));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn imports_resolve_next_to_the_importer_first() {
+        let (dir, loader, diagnostics) = load(
+            "resolve",
+            &[
+                ("song/main.tn", "import { near } from \"parts\"\nimport { far } from \"shared\""),
+                ("song/parts.tn", "instrument near { program(1) }"),
+                ("lib/parts.tn", "instrument wrong { program(2) }"),
+                ("lib/shared.tn", "instrument far { program(3) }"),
+            ],
+            &["lib"],
+        );
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let names: Vec<_> = loader.files().iter().map(|file| file.name.clone()).collect();
+        assert_eq!(names, ["song/main.tn", "song/parts.tn", "lib/shared.tn"].map(|file| dir.join(file).display().to_string()));
+        std::fs::remove_dir_all(dir).unwrap();
+        let (dir, _, diagnostics) = load("missing", &[("main.tn", "import { x } from \"nowhere\"")], &[]);
+        assert_eq!(diagnostics[0].message, "cannot find module \"nowhere\"");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_exports_point_at_the_name() {
+        let (dir, loader, diagnostics) = load(
+            "exports",
+            &[("main.tn", "import { piano, tuba } from \"band\""), ("band.tn", "instrument piano { program(1) }")],
+            &[],
+        );
+        assert!(diagnostics.is_empty());
+        let modules = loader.parse();
+        let errors = scope(&modules, 0).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "module \"band\" has no export named `tuba`");
+        assert_eq!((errors[0].location.file, errors[0].location.line, errors[0].location.col), (0, 1, 17));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
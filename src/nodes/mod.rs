@@ -10,27 +10,56 @@ pub struct ProgramNode<'src> {
 
 #[derive(Debug)]
 pub struct DeclarationNode<'src> {
-    pub staff: StaffDeclarationNode<'src>,
+    pub staff: Option<StaffDeclarationNode<'src>>,
+    pub instrument: Option<InstrumentDeclarationNode<'src>>,
+}
+#[derive(Debug)]
+pub struct InstrumentDeclarationNode<'src> {
+    pub identifier: Token<'src>,
+    pub configs: Vec<CallNode<'src>>,
 }
 #[derive(Debug)]
 pub struct StaffDeclarationNode<'src: 'src> {
+    pub docs: Vec<&'src str>,
     pub identifier: Token<'src>,
     pub staff_type: CallNode<'src>,
     pub signature: Token<'src>,
+    pub options: Vec<CallNode<'src>>,
     pub pickup: Option<PickupNode<'src>>,
     pub statements: Vec<StaffStatementNode<'src>>,
 }
 #[derive(Debug)]
 pub struct PickupNode<'src> {
+    pub calls: Vec<CallNode<'src>>,
+    pub keyword: Token<'src>,
     pub block: BlockNode<'src>,
 }
 #[derive(Debug)]
 pub struct MeasureNode<'src> {
+    pub docs: Vec<&'src str>,
+    pub keyword: Token<'src>,
     pub block: BlockNode<'src>,
 }
 #[derive(Debug)]
 pub struct BlockNode<'src> {
-    pub calls: Vec<CallWithNode<'src>>,
+    pub statements: Vec<BlockStatementNode<'src>>,
+}
+#[derive(Debug)]
+pub struct BlockStatementNode<'src> {
+    pub call: Option<CallWithNode<'src>>,
+    pub tuplet: Option<TupletNode<'src>>,
+    pub voice: Option<VoiceNode<'src>>,
+}
+#[derive(Debug)]
+pub struct TupletNode<'src> {
+    pub head: Token<'src>,
+    pub block: BlockNode<'src>,
+}
+#[derive(Debug)]
+pub struct VoiceNode<'src> {
+    pub keyword: Token<'src>,
+    pub number: Token<'src>,
+    pub block: BlockNode<'src>,
 }
 #[derive(Debug)]
 pub struct CallWithNode<'src> {
@@ -46,6 +75,26 @@ pub struct WithNode<'src> {
 pub struct StaffStatementNode<'src> {
     pub measure: Option<MeasureNode<'src>>,
     pub call: Option<CallNode<'src>>,
+    pub time: Option<TimeNode<'src>>,
+    pub repeat: Option<RepeatNode<'src>>,
+}
+#[derive(Debug)]
+pub struct TimeNode<'src> {
+    pub keyword: Token<'src>,
+    pub signature: Token<'src>,
+}
+#[derive(Debug)]
+pub struct RepeatNode<'src> {
+    pub keyword: Token<'src>,
+    pub times: Option<Token<'src>>,
+    pub statements: Vec<StaffStatementNode<'src>>,
+    pub endings: Vec<EndingNode<'src>>,
+}
+#[derive(Debug)]
+pub struct EndingNode<'src> {
+    pub keyword: Token<'src>,
+    pub passes: Vec<Token<'src>>,
+    pub statements: Vec<StaffStatementNode<'src>>,
 }
 #[derive(Debug)]
 pub struct MetaDeclarationNode<'src> {
@@ -71,16 +120,21 @@ impl<'src> ImportDeclarationNode<'src> {
     fn items(&self) -> Result<String, std::fmt::Error> {
         use std::fmt::Write;
         let mut s = String::new();
-        for item in &self.items[..self.items.len() - 1] {
-            write!(s, "{}, ", item)?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(s, ", ")?;
+            }
+            write!(s, "{}", item)?;
         }
-        write!(s, "{}, ", self.items.last().unwrap())?;
         Ok(s)
     }
 }
 
 impl<'src> fmt::Display for ImportDeclarationNode<'src> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.items.is_empty() {
+            return write!(f, "import {{}} from {}", self.source);
+        }
         write!(f, "import {{ {} }} from {}", self.items()?, self.source)
     }
 }
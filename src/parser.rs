@@ -1,12 +1,16 @@
 use std::{cell::{RefCell, Cell}};
 
-use crate::{lexer::Lexer, tokens::{Token, Keyword, Separator, TokenType::*}, nodes::*, errors::{ParseResult, ParseFinalResult}, errors::ParseError::*};
+use crate::{lexer::Lexer, tokens::{Token, Keyword, Location, Separator, Span, TokenType::*}, nodes::*, errors::{LexFinalError, ParseError, ParseFinalError, ParseOutput, ParseResult}, errors::ParseError::*};
 
 
 pub struct Parser<'src> {
     save_point: Cell<usize>,
     pos: Cell<usize>,
     tokens: RefCell<Vec<Token<'src>>>,
+    context: RefCell<Vec<(&'static str, Location)>>,
+    errors: RefCell<Vec<ParseFinalError>>,
+    // The lexer stops at its first error, so the token stream ends there.
+    lex_error: RefCell<Option<LexFinalError>>,
     lexer: Lexer<'src>,
 }
 
@@ -16,6 +20,9 @@ impl<'src> Parser<'src> {
             save_point: 0.into(),
             pos: 0.into(),
             tokens: vec![].into(),
+            context: vec![].into(),
+            errors: vec![].into(),
+            lex_error: None.into(),
             lexer,
         }
     }
@@ -24,14 +31,102 @@ impl<'src> Parser<'src> {
         if let Ok(token) = self.peek() {
             return Ok(token);
         }
+        if self.lex_error.borrow().is_some() {
+            return Err(Unknown);
+        }
         let mut tokens = self.tokens.borrow_mut();
-        tokens.push(self.lexer.lex().ok_or(Unknown)?);
+        match self.lexer.lex() {
+            Some(Ok(token)) => tokens.push(token),
+            Some(Err(err)) => {
+                *self.lex_error.borrow_mut() = Some(err);
+                return Err(Unknown);
+            }
+            None => return Err(Unknown),
+        }
         tokens.last().cloned().ok_or(Unknown)
     }
     fn prev(&self) -> ParseResult<Token<'src>> {
         self.pos.set(self.pos.get() - 1);
         self.peek()
     }
+    fn open(&self, what: &'static str, token: Token<'src>) {
+        self.context.borrow_mut().push((what, token.location()));
+    }
+    fn close(&self) {
+        self.context.borrow_mut().pop();
+    }
+    fn fail(&self, error: ParseError) -> ParseFinalError {
+        if let (Err(_), Some((err, location))) = (self.peek(), self.lex_error.borrow().clone()) {
+            return ParseFinalError { error: ParseError::Lex(err), found: String::new(), location, context: self.context.borrow().clone() };
+        }
+        let (found, location) = match self.peek() {
+            Ok(token) => (token.describe(), token.location()),
+            Err(_) if self.lexer.is_done() => ("end of file".to_string(), self.lexer.location()),
+            Err(_) => ("an unrecognised token".to_string(), self.lexer.location()),
+        };
+        ParseFinalError { error, found, location, context: self.context.borrow().clone() }
+    }
+    fn report(&self, error: ParseError) {
+        let error = self.fail(error);
+        let mut errors = self.errors.borrow_mut();
+        if matches!(error.error, ParseError::Lex(_)) && errors.iter().any(|reported| matches!(reported.error, ParseError::Lex(_))) {
+            return;
+        }
+        errors.push(error);
+    }
+    fn lookahead(&self) -> ParseResult<Token<'src>> {
+        let token = self.next();
+        self.pos.set(self.pos.get() - 1);
+        token
+    }
+    // Skips the rest of a broken declaration. Returns false when the end of the input is reached.
+    fn synchronize_declaration(&self, resume: &[Keyword]) -> bool {
+        self.context.borrow_mut().clear();
+        let mut token = self.peek();
+        loop {
+            match token {
+                Ok(Token::Keyword(keyword, _)) if resume.contains(&keyword) => {
+                    self.pos.set(self.pos.get() - 1);
+                    return true;
+                }
+                Ok(Token::EOF(_)) | Err(_) => return false,
+                _ => {}
+            }
+            token = self.next();
+        }
+    }
+    // Skips the rest of a broken staff statement. Returns true when another statement of the same
+    // staff follows, and false once the staff has ended.
+    fn synchronize_statement(&self, depth: usize) -> bool {
+        self.context.borrow_mut().truncate(depth);
+        let mut token = self.peek();
+        loop {
+            match token {
+                Ok(Token::Keyword(Keyword::Measure | Keyword::Time | Keyword::Repeat, _)) => {
+                    self.pos.set(self.pos.get() - 1);
+                    return true;
+                }
+                Ok(Token::Keyword(Keyword::Staff | Keyword::Instrument, _)) => {
+                    self.pos.set(self.pos.get() - 1);
+                    return false;
+                }
+                Ok(Token::Separator(Separator::RCurly, _)) => match self.lookahead() {
+                    Ok(Token::Keyword(Keyword::Measure | Keyword::Time | Keyword::Repeat, _) | Token::Identifier(_, _)) => return true,
+                    Ok(Token::Keyword(Keyword::Staff | Keyword::Instrument, _) | Token::EOF(_)) | Err(_) => return false,
+                    _ => {}
+                },
+                Ok(Token::EOF(_)) | Err(_) => return false,
+                _ => {}
+            }
+            token = self.next();
+        }
+    }
+    // Statements may end with a `;`, as older scores write them.
+    fn semicolon(&self) {
+        if matches!(self.lookahead(), Ok(Token::Separator(Separator::Semicolon, _))) {
+            let _ = self.next();
+        }
+    }
     fn quicksave(&self) {
         self.save_point.set(self.pos.get());
     }
@@ -39,7 +134,8 @@ impl<'src> Parser<'src> {
         self.pos.set(self.save_point.get());
     }
     fn peek(&self) -> ParseResult<Token<'src>> {
-        self.tokens.borrow().get(self.pos.get() - 1).cloned().ok_or(Unknown)
+        let pos = self.pos.get().checked_sub(1).ok_or(Unknown)?;
+        self.tokens.borrow().get(pos).cloned().ok_or(Unknown)
     }
     fn next_literal(&self) -> ParseResult<Token<'src>> {
         match self.next() {
@@ -50,6 +146,15 @@ impl<'src> Parser<'src> {
     fn next_identifier(&self) -> ParseResult<Token<'src>> {
         match self.next() {
             Ok(token @ Token::Identifier(_, _)) => Ok(token),
+            // Names such as `staff A` lex as notes.
+            Ok(Token::Note(_, _, _, location)) => Ok(Token::Identifier(self.lexer.text(location), location)),
+            _ => Err(ExpectedType(Identifier))
+        }
+    }
+    // Note values may be written as numbers, as in `8(C)`.
+    fn next_call_head(&self) -> ParseResult<Token<'src>> {
+        match self.next() {
+            Ok(token @ (Token::Identifier(_, _) | Token::Number(_, _))) => Ok(token),
             _ => Err(ExpectedType(Identifier))
         }
     }
@@ -59,51 +164,113 @@ impl<'src> Parser<'src> {
             _ => Err(ExpectedType(Signature))
         }
     }
-    pub fn parse(&self) -> ParseFinalResult<ProgramNode> {
-        match self.parse_inner() {
-            Ok(node) => Ok(node),
-            Err(err) => Err((err, match self.prev().unwrap() {
-                Token::Separator(_, loc) => loc,
-                Token::Literal(_, loc) => loc,
-                Token::Identifier(_, loc) => loc,
-                Token::Signature(_, _, loc) => loc,
-                Token::Number(_, loc) => loc,
-                Token::Note(_, _, loc) => loc,
-                Token::Keyword(_, loc) => loc,
-                Token::EOF(loc) => loc
-            }))
+    pub fn parse(&self) -> ParseOutput<ProgramNode<'src>> {
+        let node = self.parse_inner();
+        let mut errors = self.errors.take();
+        // A lexer error at the end of a file can stop the parse without any rule failing.
+        if let Some((err, location)) = self.lex_error.take() {
+            if !errors.iter().any(|reported| matches!(reported.error, ParseError::Lex(_))) {
+                errors.push(ParseFinalError { error: ParseError::Lex(err), found: String::new(), location, context: vec![] });
+            }
         }
+        (node, errors)
     }
-    fn parse_inner(&self) -> ParseResult<ProgramNode> {
+    fn parse_inner(&self) -> ProgramNode<'src> {
         let mut imports = vec![];
         loop {
             self.quicksave();
-            if let Some(import) = self.import() {
-                imports.push(import?);
-                continue;
+            match self.import() {
+                Some(Ok(import)) => imports.push(import),
+                Some(Err(err)) => {
+                    self.report(err);
+                    self.synchronize_declaration(&[Keyword::Import, Keyword::Meta, Keyword::Staff, Keyword::Instrument]);
+                }
+                None => {
+                    self.restore();
+                    break;
+                }
             }
-            self.restore();
-            break;
         }
-        let meta = self.meta()?;
+        // Modules that only export declarations may leave the meta block out.
+        let meta = loop {
+            let next = self.lookahead();
+            if matches!(next, Ok(Token::Keyword(Keyword::Staff | Keyword::Instrument, _) | Token::EOF(_))) || next.is_err() {
+                break MetaDeclarationNode { configs: vec![] };
+            }
+            match self.meta() {
+                Ok(meta) => break meta,
+                Err(err) => {
+                    self.report(err);
+                    let resumed = self.synchronize_declaration(&[Keyword::Meta, Keyword::Staff, Keyword::Instrument]);
+                    if resumed && matches!(self.lookahead(), Ok(Token::Keyword(Keyword::Meta, _))) {
+                        continue;
+                    }
+                    break MetaDeclarationNode { configs: vec![] };
+                }
+            }
+        };
         let mut declarations = vec![];
-        while !matches!(self.peek(), Ok(Token::EOF(_))) {
-            if let Some(node) = self.declaration() {
-                declarations.push(node?);
-                continue;
+        while !matches!(self.lookahead(), Ok(Token::EOF(_))) {
+            match self.declaration() {
+                Some(Ok(node)) => declarations.push(node),
+                Some(Err(err)) => {
+                    self.report(err);
+                    if !self.synchronize_declaration(&[Keyword::Staff, Keyword::Instrument]) {
+                        break;
+                    }
+                }
+                None if self.peek().is_err() => break,
+                None => {
+                    self.report(ExpectedKeyword(Keyword::Staff));
+                    if !self.synchronize_declaration(&[Keyword::Staff, Keyword::Instrument]) {
+                        break;
+                    }
+                }
             }
-            break;
         }
-        Ok(ProgramNode { imports, meta, declarations })
+        ProgramNode { imports, meta, declarations }
     }
-    fn declaration(&self) -> Option<ParseResult<DeclarationNode>> {
+    fn declaration(&self) -> Option<ParseResult<DeclarationNode<'src>>> {
+        self.quicksave();
         match self.staff() {
+            Some(Ok(staff)) => return Some(Ok(DeclarationNode { staff: Some(staff), instrument: None })),
+            Some(Err(err)) => return Some(Err(err)),
+            None => self.restore()
+        }
+        match self.instrument() {
             None => None,
-            Some(Ok(staff)) => Some(Ok(DeclarationNode { staff })),
+            Some(Ok(instrument)) => Some(Ok(DeclarationNode { staff: None, instrument: Some(instrument) })),
             Some(Err(err)) => Some(Err(err))
         }
     }
-    fn import(&self) -> Option<ParseResult<ImportDeclarationNode>> {
+    fn instrument(&self) -> Option<ParseResult<InstrumentDeclarationNode<'src>>> {
+        let keyword = match self.next() {
+            Ok(token @ Token::Keyword(Keyword::Instrument, _)) => token,
+            _ => return None
+        };
+        self.open("instrument declared here", keyword);
+        let instrument = self.instrument_inner();
+        if instrument.is_ok() {
+            self.close();
+        }
+        Some(instrument)
+    }
+    fn instrument_inner(&self) -> ParseResult<InstrumentDeclarationNode<'src>> {
+        let identifier = self.next_identifier()?;
+        if !matches!(self.next(), Ok(Token::Separator(Separator::LCurly, _))) {
+            return Err(ExpectedSeparator(Separator::LCurly));
+        }
+        let mut configs = vec![];
+        while let Some(call) = self.call() {
+            configs.push(call?);
+            self.semicolon();
+        }
+        if !matches!(self.peek(), Ok(Token::Separator(Separator::RCurly, _))) {
+            return Err(ExpectedSeparator(Separator::RCurly));
+        }
+        Ok(InstrumentDeclarationNode { identifier, configs })
+    }
+    fn import(&self) -> Option<ParseResult<ImportDeclarationNode<'src>>> {
         if !matches!(self.next(), Ok(Token::Keyword(Keyword::Import, _))) {
             return None;
         }
@@ -124,17 +291,26 @@ impl<'src> Parser<'src> {
             return Some(Err(ExpectedKeyword(Keyword::From)));
         }
         Some(match self.next_literal() {
-            Ok(source) => Ok(ImportDeclarationNode { items, source }),
+            Ok(source) => {
+                self.semicolon();
+                Ok(ImportDeclarationNode { items, source })
+            }
             Err(err) => Err(err)
         })
     }
-    fn staff(&self) -> Option<ParseResult<StaffDeclarationNode>> {
-        if !matches!(self.next(), Ok(Token::Keyword(Keyword::Staff, _))) {
-            return None;
+    fn staff(&self) -> Option<ParseResult<StaffDeclarationNode<'src>>> {
+        let keyword = match self.next() {
+            Ok(token @ Token::Keyword(Keyword::Staff, _)) => token,
+            _ => return None
+        };
+        self.open("staff declared here", keyword);
+        let staff = self.staff_inner(self.lexer.docs(keyword.location()));
+        if staff.is_ok() {
+            self.close();
         }
-        Some(self.staff_inner())
+        Some(staff)
     }
-    fn staff_inner(&self) -> ParseResult<StaffDeclarationNode> {
+    fn staff_inner(&self, docs: Vec<&'src str>) -> ParseResult<StaffDeclarationNode<'src>> {
         let identifier = self.next_identifier()?;
         if !matches!(self.next(), Ok(Token::Keyword(Keyword::Is, _))) {
             return Err(ExpectedKeyword(Keyword::Is));
@@ -144,30 +320,69 @@ impl<'src> Parser<'src> {
             return Err(ExpectedKeyword(Keyword::In));
         }
         let signature = self.next_signature()?;
+        let mut options = vec![];
+        while matches!(self.lookahead(), Ok(Token::Keyword(Keyword::With, _))) {
+            self.next()?;
+            options.push(self.call().ok_or(ExpectedType(Identifier))??);
+        }
         if !matches!(self.next(), Ok(Token::Separator(Separator::LCurly, _))) {
             return Err(ExpectedSeparator(Separator::LCurly));
         }
+        let depth = self.context.borrow().len();
+        let mut open = true;
+        // Directives such as `bpm(100);` may come before the pickup, which they then apply to.
+        let start = self.pos.get();
+        let mut calls = vec![];
+        loop {
+            self.quicksave();
+            match self.call() {
+                Some(Ok(call)) => {
+                    self.semicolon();
+                    calls.push(call);
+                }
+                _ => {
+                    self.restore();
+                    break;
+                }
+            }
+        }
         let pickup = match self.pickup() {
-            Some(node) => Some(node?),
-            None => None
+            Some(Ok(node)) => Some(PickupNode { calls, ..node }),
+            Some(Err(err)) => {
+                self.report(err);
+                open = self.synchronize_statement(depth);
+                None
+            }
+            None => {
+                self.pos.set(start);
+                None
+            }
         };
 
         let mut statements = vec![];
-        while let Some(statement) = self.staff_statement() {
-            statements.push(statement?);
-        }
-        if !matches!(self.peek(), Ok(Token::Separator(Separator::RCurly, _))) {
-            return Err(ExpectedSeparator(Separator::RCurly));
+        while open {
+            match self.staff_statement() {
+                Some(Ok(statement)) => {
+                    statements.push(statement);
+                    continue;
+                }
+                Some(Err(err)) => self.report(err),
+                None if matches!(self.peek(), Ok(Token::Separator(Separator::RCurly, _))) => break,
+                None => self.report(ExpectedSeparator(Separator::RCurly)),
+            }
+            open = self.synchronize_statement(depth);
         }
         Ok(StaffDeclarationNode {
+            docs,
             identifier,
             staff_type,
             signature,
+            options,
             pickup,
             statements,
         })
     }
-    fn staff_statement(&self) -> Option<ParseResult<StaffStatementNode>> {
+    fn staff_statement(&self) -> Option<ParseResult<StaffStatementNode<'src>>> {
         self.quicksave();
         if let Some(measure) = self.measure() {
             let measure = match measure {
@@ -176,29 +391,138 @@ impl<'src> Parser<'src> {
             };
             return Some(Ok(StaffStatementNode {
                 measure: Some(measure),
-                call: None
+                call: None,
+                time: None,
+                repeat: None
             }))
         }
         self.restore();
+        if let Ok(keyword @ Token::Keyword(Keyword::Time, _)) = self.next() {
+            return Some(match self.next_signature() {
+                Ok(signature) => Ok(StaffStatementNode {
+                    measure: None,
+                    call: None,
+                    time: Some(TimeNode { keyword, signature }),
+                    repeat: None
+                }),
+                Err(err) => Err(err)
+            });
+        }
+        self.restore();
+        if let Some(repeat) = self.repeat() {
+            return Some(match repeat {
+                Ok(repeat) => Ok(StaffStatementNode {
+                    measure: None,
+                    call: None,
+                    time: None,
+                    repeat: Some(repeat)
+                }),
+                Err(err) => Err(err)
+            });
+        }
+        self.restore();
         match self.call() {
             None => None,
-            Some(Ok(call)) => Some(Ok(StaffStatementNode {
-                measure: None,
-                call: Some(call)
-            })),
+            Some(Ok(call)) => {
+                self.semicolon();
+                Some(Ok(StaffStatementNode {
+                    measure: None,
+                    call: Some(call),
+                    time: None,
+                    repeat: None
+                }))
+            }
             Some(Err(err)) => Some(Err(err))
         }
     }
-    fn meta(&self) -> ParseResult<MetaDeclarationNode> {
-        if !matches!(self.next(), Ok(Token::Keyword(Keyword::Meta, _))) {
-            return Err(ExpectedKeyword(Keyword::Meta));
+    // Statements nested in a repeat or an ending, up to the `}` or `ending` that follows them.
+    fn nested_statements(&self) -> ParseResult<Vec<StaffStatementNode<'src>>> {
+        let mut statements = vec![];
+        loop {
+            let pos = self.pos.get();
+            match self.staff_statement() {
+                Some(statement) => statements.push(statement?),
+                None => {
+                    self.pos.set(pos);
+                    return match self.lookahead() {
+                        Ok(Token::Separator(Separator::RCurly, _) | Token::Keyword(Keyword::Ending, _)) => Ok(statements),
+                        _ => {
+                            self.next()?;
+                            Err(ExpectedSeparator(Separator::RCurly))
+                        }
+                    };
+                }
+            }
+        }
+    }
+    fn repeat(&self) -> Option<ParseResult<RepeatNode<'src>>> {
+        let keyword = match self.next() {
+            Ok(token @ Token::Keyword(Keyword::Repeat, _)) => token,
+            _ => return None
+        };
+        self.open("repeat opened here", keyword);
+        let repeat = self.repeat_inner(keyword);
+        if repeat.is_ok() {
+            self.close();
+        }
+        Some(repeat)
+    }
+    fn repeat_inner(&self, keyword: Token<'src>) -> ParseResult<RepeatNode<'src>> {
+        let times = match self.lookahead() {
+            Ok(token @ Token::Number(_, _)) => {
+                self.next()?;
+                Some(token)
+            }
+            _ => None
+        };
+        if !matches!(self.next(), Ok(Token::Separator(Separator::LCurly, _))) {
+            return Err(ExpectedSeparator(Separator::LCurly));
+        }
+        let statements = self.nested_statements()?;
+        let mut endings = vec![];
+        while let Ok(keyword @ Token::Keyword(Keyword::Ending, _)) = self.lookahead() {
+            self.next()?;
+            self.open("ending opened here", keyword);
+            endings.push(self.ending(keyword)?);
+            self.close();
+        }
+        if !matches!(self.next(), Ok(Token::Separator(Separator::RCurly, _))) {
+            return Err(ExpectedSeparator(Separator::RCurly));
+        }
+        Ok(RepeatNode { keyword, times, statements, endings })
+    }
+    fn ending(&self, keyword: Token<'src>) -> ParseResult<EndingNode<'src>> {
+        let mut passes = vec![];
+        loop {
+            match self.next() {
+                Ok(token @ Token::Number(_, _)) => passes.push(token),
+                _ => return Err(ExpectedType(Number))
+            }
+            match self.next() {
+                Ok(Token::Separator(Separator::Comma, _)) => continue,
+                Ok(Token::Separator(Separator::LCurly, _)) => break,
+                _ => return Err(ExpectedSeparator(Separator::LCurly))
+            }
+        }
+        let statements = self.nested_statements()?;
+        if !matches!(self.next(), Ok(Token::Separator(Separator::RCurly, _))) {
+            return Err(ExpectedSeparator(Separator::RCurly));
         }
+        Ok(EndingNode { keyword, passes, statements })
+    }
+    fn meta(&self) -> ParseResult<MetaDeclarationNode<'src>> {
+        let keyword = match self.next() {
+            Ok(token @ Token::Keyword(Keyword::Meta, _)) => token,
+            _ => return Err(ExpectedKeyword(Keyword::Meta))
+        };
+        self.open("meta block opened here", keyword);
         if !matches!(self.next(), Ok(Token::Separator(Separator::LCurly, _))) {
             return Err(ExpectedSeparator(Separator::LCurly));
         }
         let mut configs = vec![];
         while let Ok(call) = self.call().ok_or(ExpectedType(Identifier)) {
             configs.push(call?);
+            self.semicolon();
         }
         if configs.is_empty() {
             return Err(EmptyMeta);
@@ -206,29 +530,38 @@ impl<'src> Parser<'src> {
         if !matches!(self.peek(), Ok(Token::Separator(Separator::RCurly, _))) {
             return Err(ExpectedSeparator(Separator::RCurly));
         }
+        self.close();
         Ok(MetaDeclarationNode { configs })
     }
-    fn pickup(&self) -> Option<ParseResult<PickupNode>> {
-        if !matches!(self.next(), Ok(Token::Keyword(Keyword::Pickup, _))) {
-            return None;
-        }
+    fn pickup(&self) -> Option<ParseResult<PickupNode<'src>>> {
+        let keyword = match self.next() {
+            Ok(token @ Token::Keyword(Keyword::Pickup, _)) => token,
+            _ => return None
+        };
+        self.open("pickup opened here", keyword);
         Some(match self.block() {
-            Ok(block) => Ok(PickupNode {block}),
+            Ok(block) => {
+                self.close();
+                Ok(PickupNode {calls: vec![], keyword, block})
+            }
             Err(err) => Err(err)
         })
     }
-    fn measure(&self) -> Option<ParseResult<MeasureNode>> {
-        if !matches!(self.next(), Ok(Token::Keyword(Keyword::Measure, _))) {
-            return None;
-        }
+    fn measure(&self) -> Option<ParseResult<MeasureNode<'src>>> {
+        let keyword = match self.next() {
+            Ok(token @ Token::Keyword(Keyword::Measure, _)) => token,
+            _ => return None
+        };
+        self.open("measure opened here", keyword);
         let block = match self.block() {
             Ok(block) => block,
             Err(err) => return Some(Err(err))
         };
-        Some(Ok(MeasureNode {block}))
+        self.close();
+        Some(Ok(MeasureNode {docs: self.lexer.docs(keyword.location()), keyword, block}))
     }
-    fn call(&self) -> Option<ParseResult<CallNode>> {
-        let identifier = match self.next_identifier() {
+    fn call(&self) -> Option<ParseResult<CallNode<'src>>> {
+        let identifier = match self.next_call_head() {
             Ok(identifier) => identifier,
             Err(_) => return None
         };
@@ -236,6 +569,10 @@ impl<'src> Parser<'src> {
             return None;
         }
         let mut arguments = vec![];
+        if matches!(self.lookahead(), Ok(Token::Separator(Separator::RParan, _))) {
+            self.next().ok()?;
+            return Some(Ok(CallNode { identifier, arguments }));
+        }
         loop {
             let argument = match self.argument() {
                 Ok(argument) => argument,
@@ -254,10 +591,10 @@ impl<'src> Parser<'src> {
             arguments,
         }))
     }
-    fn call_with(&self) -> Option<ParseResult<CallWithNode>> {
+    fn call_with(&self) -> Option<ParseResult<CallWithNode<'src>>> {
         let call = match self.call()? {
             Ok(call) => call,
-            Err(_) => return None
+            Err(err) => return Some(Err(err))
         };
         let mut with = vec![];
         loop {
@@ -285,11 +622,12 @@ impl<'src> Parser<'src> {
         }
         Some(Ok(CallWithNode {call, with}))
     }
-    fn argument(&self) -> ParseResult<ArgumentNode> {
+    fn argument(&self) -> ParseResult<ArgumentNode<'src>> {
         let argument = match self.next() {
+            Ok(head @ Token::Identifier("chord", _)) if matches!(self.lookahead(), Ok(Token::Separator(Separator::LParan, _))) => self.chord(head),
             Ok(token @ Token::Literal(_, _)) => Ok(token),
             Ok(token @ Token::Identifier(_, _)) => Ok(token),
-            Ok(token @ Token::Note(_, _, _)) => Ok(token),
+            Ok(token @ Token::Note(_, _, _, _)) => Ok(token),
             Ok(token @ Token::Number(_, _)) => Ok(token),
             _ => Err(ExpectedArgument)
         }?;
@@ -297,17 +635,65 @@ impl<'src> Parser<'src> {
             argument
         })
     }
-    fn block(&self) -> ParseResult<BlockNode> {
+    // Chord symbols are marked, as many of them also read as notes: `chord(G7)` is not `G7`.
+    fn chord(&self, head: Token<'src>) -> ParseResult<Token<'src>> {
+        self.next()?;
+        let symbol = match self.next() {
+            Ok(token @ (Token::Identifier(_, _) | Token::Note(_, _, _, _))) => self.lexer.text(token.location()),
+            _ => return Err(ExpectedType(Chord)),
+        };
+        if !matches!(self.next(), Ok(Token::Separator(Separator::RParan, _))) {
+            return Err(ExpectedSeparator(Separator::RParan));
+        }
+        let start = head.location();
+        let end = self.peek()?.location().span.end;
+        Ok(Token::Chord(symbol, Location { span: Span { start: start.span.start, end }, ..start }))
+    }
+    fn block(&self) -> ParseResult<BlockNode<'src>> {
         if !matches!(self.next(), Ok(Token::Separator(Separator::LCurly, _))) {
             return Err(ExpectedSeparator(Separator::LCurly));
         }
-        let mut calls = vec![];
-        while let Some(call) = self.call_with() {
-            calls.push(call?);
+        let mut statements = vec![];
+        while let Some(statement) = self.block_statement() {
+            statements.push(statement?);
         }
         if !matches!(self.peek(), Ok(Token::Separator(Separator::RCurly, _))) {
             return Err(ExpectedSeparator(Separator::RCurly));
         }
-        Ok(BlockNode { calls })
+        Ok(BlockNode { statements })
+    }
+    fn block_statement(&self) -> Option<ParseResult<BlockStatementNode<'src>>> {
+        self.quicksave();
+        let head = self.next();
+        if let Ok(keyword @ Token::Keyword(Keyword::Voice, _)) = head {
+            self.open("voice opened here", keyword);
+            let number = match self.next() {
+                Ok(token @ Token::Number(_, _)) => token,
+                _ => return Some(Err(ExpectedType(Number)))
+            };
+            let block = match self.block() {
+                Ok(block) => block,
+                Err(err) => return Some(Err(err))
+            };
+            self.close();
+            return Some(Ok(BlockStatementNode { call: None, tuplet: None, voice: Some(VoiceNode { keyword, number, block }) }));
+        }
+        if let (Ok(head @ (Token::Identifier(_, _) | Token::Ratio(_, _, _))), Ok(Token::Separator(Separator::LCurly, _))) = (head, self.lookahead()) {
+            self.open("tuplet opened here", head);
+            let block = match self.block() {
+                Ok(block) => block,
+                Err(err) => return Some(Err(err))
+            };
+            self.close();
+            return Some(Ok(BlockStatementNode { call: None, tuplet: Some(TupletNode { head, block }), voice: None }));
+        }
+        self.restore();
+        Some(match self.call_with()? {
+            Ok(call) => {
+                self.semicolon();
+                Ok(BlockStatementNode { call: Some(call), tuplet: None, voice: None })
+            }
+            Err(err) => Err(err)
+        })
     }
 }
\ No newline at end of file
@@ -0,0 +1,624 @@
+use crate::tokens::{Location, Note, Token};
+pub(super) use std::{cmp::Ordering, fmt, ops};
+
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
+#[derive(Debug)]
+pub struct Score<'src> {
+    pub title: Option<&'src str>,
+    pub composer: Option<&'src str>,
+    pub description: Option<&'src str>,
+    pub staves: Vec<Staff<'src>>,
+    pub tempo: TempoMap,
+    pub velocities: VelocityTable,
+}
+
+// Microseconds per quarter note from each position onwards, shared by every staff.
+#[derive(Debug, Default)]
+pub struct TempoMap {
+    pub changes: Vec<(Fraction, u32)>,
+}
+
+// MIDI velocities for `ppp` through `fff`, followed by `sfz`.
+#[derive(Debug, Clone, Copy)]
+pub struct VelocityTable(pub [u8; 9]);
+
+#[derive(Debug)]
+pub struct Staff<'src> {
+    pub docs: Vec<&'src str>,
+    pub name: &'src str,
+    pub clef: Clef,
+    pub instrument: Option<Instrument<'src>>,
+    pub pickup: Option<Measure<'src>>,
+    pub measures: Vec<Measure<'src>>,
+    pub navigation: Navigation,
+    pub location: Location,
+}
+
+// Repeats and jumps as written. Positions are indices into `Staff::measures`, where a mark at `n`
+// sits just before measure `n`.
+#[derive(Debug, Default)]
+pub struct Navigation {
+    pub repeats: Vec<Repeat>,
+    pub segno: Option<usize>,
+    pub coda: Option<usize>,
+    pub to_coda: Option<usize>,
+    pub fine: Option<usize>,
+    pub jump: Option<Jump>,
+}
+
+#[derive(Debug)]
+pub struct Repeat {
+    pub start: usize,
+    pub end: usize,
+    pub times: u32,
+    pub endings: Vec<Ending>,
+}
+
+#[derive(Debug)]
+pub struct Ending {
+    pub passes: Vec<u32>,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Jump {
+    pub at: usize,
+    pub to: JumpTarget,
+    pub until: JumpEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpTarget {
+    Start,
+    Segno,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpEnd {
+    End,
+    Fine,
+    Coda,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Instrument<'src> {
+    pub name: &'src str,
+    pub program: u8,
+    pub percussion: bool,
+    pub clef: Clef,
+    pub range: Option<(Pitch, Pitch)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clef {
+    Treble,
+    Bass,
+    Alto,
+    Tenor,
+    Percussion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub top: u32,
+    pub bottom: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub tonic: Note,
+    pub mode: Mode,
+}
+
+#[derive(Debug)]
+pub struct Measure<'src> {
+    pub docs: Vec<&'src str>,
+    pub key: Key,
+    pub signature: TimeSignature,
+    pub directives: Vec<(Directive, Location)>,
+    pub voices: Vec<Voice<'src>>,
+    pub location: Location,
+}
+
+// A measure without voice blocks holds a single voice numbered 1.
+#[derive(Debug)]
+pub struct Voice<'src> {
+    pub number: u32,
+    pub events: Vec<Event<'src>>,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Directive {
+    Tempo(u32),
+    Ramp { target: u32, measures: u32, faster: bool },
+}
+
+#[derive(Debug)]
+pub struct Event<'src> {
+    pub pitches: Vec<Pitch>,
+    // Set when the pitches were voiced from a chord symbol rather than written out.
+    pub symbol: bool,
+    pub duration: Duration,
+    pub modifiers: Vec<Modifier<'src>>,
+    pub tie: bool,
+    pub legato: bool,
+    pub articulations: Vec<Articulation>,
+    pub dynamic: Option<Dynamic>,
+    pub hairpin: Option<Hairpin>,
+    pub velocity: u8,
+    pub location: Location,
+}
+
+// Ornaments carry the pitch they alternate with, or the grace note to play.
+#[derive(Debug, Clone, Copy)]
+pub enum Articulation {
+    Staccato,
+    Staccatissimo,
+    Tenuto,
+    Accent,
+    Marcato,
+    Tremolo,
+    Trill(Pitch),
+    Mordent(Pitch),
+    Grace(Pitch),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dynamic {
+    Ppp,
+    Pp,
+    P,
+    Mp,
+    Mf,
+    F,
+    Ff,
+    Fff,
+    Sfz,
+    Fp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hairpin {
+    Crescendo,
+    Diminuendo,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Pitch {
+    pub note: Note,
+    pub octave: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Duration {
+    pub value: u32,
+    pub dots: u8,
+    pub scale: Fraction,
+}
+
+#[derive(Debug)]
+pub struct Modifier<'src> {
+    pub name: &'src str,
+    pub arguments: Vec<Token<'src>>,
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fraction {
+    num: u64,
+    den: u64,
+}
+
+impl TimeSignature {
+    pub fn length(&self) -> Fraction {
+        Fraction::new(self.top as u64, self.bottom as u64)
+    }
+    // Compound meters such as 6/8 beat in dotted notes; irregular ones such as 7/8 group
+    // into twos with a closing three.
+    pub fn beats(&self) -> Vec<Fraction> {
+        let unit = Fraction::new(1, self.bottom as u64);
+        let groups = if self.bottom < 8 || self.top <= 3 {
+            vec![1; self.top as usize]
+        } else if self.top.is_multiple_of(3) {
+            vec![3; self.top as usize / 3]
+        } else {
+            let mut groups = vec![2; self.top as usize / 2];
+            if !self.top.is_multiple_of(2) {
+                *groups.last_mut().unwrap_or(&mut 0) = 3;
+            }
+            groups
+        };
+        groups.into_iter().map(|group| unit * Fraction::new(group, 1)).collect()
+    }
+    // Counts a length in the signature's unit where it divides evenly, so half a bar of 6/8 is 3/8.
+    pub fn written(&self, length: Fraction) -> String {
+        let units = length * Fraction::new(self.bottom as u64, 1);
+        match units.den() {
+            1 => format!("{}/{}", units.num(), self.bottom),
+            _ => length.to_string(),
+        }
+    }
+}
+
+impl Pitch {
+    pub fn midi(&self) -> i32 {
+        (self.octave as i32 + 1) * 12 + self.note.semitone() as i32
+    }
+    // Moves by scale steps in a key, as an ornament does to find its neighbouring note.
+    pub fn step(&self, key: Key, steps: i8) -> Pitch {
+        let letters = "CDEFGAB";
+        let index = letters.find(self.note.letter()).unwrap_or(0) as i32 + self.octave as i32 * 7 + steps as i32;
+        let letter = letters.chars().nth(index.rem_euclid(7) as usize).unwrap_or('C');
+        let note = Note::spell(letter, 0).map_or(Note::C, |note| key.apply(note));
+        Pitch { note, octave: index.div_euclid(7).clamp(0, 9) as u8 }
+    }
+}
+
+impl Articulation {
+    // The share of the written length that sounds.
+    pub fn gate(&self) -> Option<Fraction> {
+        match self {
+            Articulation::Staccato => Some(Fraction::new(1, 2)),
+            Articulation::Staccatissimo => Some(Fraction::new(1, 4)),
+            _ => None,
+        }
+    }
+    // Velocity added on top of the dynamic in force.
+    pub fn accent(&self) -> u8 {
+        match self {
+            Articulation::Tenuto => 8,
+            Articulation::Accent => 16,
+            Articulation::Marcato => 32,
+            _ => 0,
+        }
+    }
+}
+
+impl Dynamic {
+    pub fn parse(name: &str) -> Option<Dynamic> {
+        Some(match name {
+            "ppp" => Dynamic::Ppp,
+            "pp" => Dynamic::Pp,
+            "p" => Dynamic::P,
+            "mp" => Dynamic::Mp,
+            "mf" => Dynamic::Mf,
+            "f" => Dynamic::F,
+            "ff" => Dynamic::Ff,
+            "fff" => Dynamic::Fff,
+            "sfz" => Dynamic::Sfz,
+            "fp" => Dynamic::Fp,
+            _ => return None,
+        })
+    }
+    // The level that carries on to the following notes. An `sfz` only accents its own note.
+    pub fn after(self, level: Dynamic) -> Dynamic {
+        match self {
+            Dynamic::Sfz => level,
+            Dynamic::Fp => Dynamic::P,
+            dynamic => dynamic,
+        }
+    }
+}
+
+impl VelocityTable {
+    pub fn get(&self, dynamic: Dynamic) -> u8 {
+        match dynamic {
+            Dynamic::Fp => self.0[Dynamic::F as usize],
+            dynamic => self.0[dynamic as usize],
+        }
+    }
+    pub fn set(&mut self, dynamic: Dynamic, velocity: u8) {
+        let index = if dynamic == Dynamic::Fp { Dynamic::F } else { dynamic };
+        self.0[index as usize] = velocity;
+    }
+}
+
+impl Default for VelocityTable {
+    fn default() -> Self {
+        Self([16, 33, 49, 64, 80, 96, 112, 127, 120])
+    }
+}
+
+impl Repeat {
+    // The position after the last ending, or after the body when there are none.
+    pub fn last(&self) -> usize {
+        self.endings.last().map_or(self.end, |ending| ending.end)
+    }
+}
+
+impl Score<'_> {
+    // The MIDI channel of each staff. Staffs with the same program share a channel, so a score may
+    // have more staffs than there are channels. `None` marks a staff left without one.
+    pub fn channels(&self) -> Vec<Option<u8>> {
+        let mut programs = vec![];
+        self.staves
+            .iter()
+            .map(|staff| {
+                if staff.percussion() {
+                    return Some(PERCUSSION_CHANNEL);
+                }
+                let program = staff.instrument.as_ref().map(|instrument| instrument.program);
+                let index = programs.iter().position(|other| *other == program).unwrap_or_else(|| {
+                    programs.push(program);
+                    programs.len() - 1
+                });
+                let channel = if index < PERCUSSION_CHANNEL as usize { index } else { index + 1 };
+                (channel < 16).then_some(channel as u8)
+            })
+            .collect()
+    }
+}
+
+impl<'src> Staff<'src> {
+    pub fn percussion(&self) -> bool {
+        self.clef == Clef::Percussion || self.instrument.as_ref().is_some_and(|instrument| instrument.percussion)
+    }
+    // Every voice number used on the staff, in ascending order.
+    pub fn voices(&self) -> Vec<u32> {
+        let mut numbers: Vec<u32> = self.pickup.iter().chain(&self.measures).flat_map(|measure| &measure.voices).map(|voice| voice.number).collect();
+        numbers.sort();
+        numbers.dedup();
+        numbers
+    }
+    // Where each event of a voice starts, counted from the start of the staff as written.
+    pub fn voice_positions(&self, number: u32) -> Vec<Fraction> {
+        let mut positions = vec![];
+        let mut start = Fraction::zero();
+        for measure in self.pickup.iter().chain(&self.measures) {
+            for voice in measure.voices.iter().filter(|voice| voice.number == number) {
+                let mut position = start;
+                for event in &voice.events {
+                    positions.push(position);
+                    position = position + event.duration.length();
+                }
+            }
+            start = start + measure.length();
+        }
+        positions
+    }
+    // The events of one voice through the whole staff, as written.
+    pub fn voice_events_mut(&mut self, number: u32) -> Vec<&mut Event<'src>> {
+        self.pickup
+            .iter_mut()
+            .chain(&mut self.measures)
+            .flat_map(|measure| &mut measure.voices)
+            .filter(|voice| voice.number == number)
+            .flat_map(|voice| &mut voice.events)
+            .collect()
+    }
+}
+
+impl<'src> Measure<'src> {
+    // The longest voice decides where the next measure starts.
+    pub fn length(&self) -> Fraction {
+        self.voices.iter().map(Voice::length).max().unwrap_or(Fraction::zero())
+    }
+    pub fn events(&self) -> impl Iterator<Item = &Event<'src>> {
+        self.voices.iter().flat_map(|voice| &voice.events)
+    }
+}
+
+impl Voice<'_> {
+    pub fn length(&self) -> Fraction {
+        self.events.iter().fold(Fraction::zero(), |total, event| total + event.duration.length())
+    }
+}
+
+impl Key {
+    pub fn new(tonic: Note, mode: Mode) -> Option<Key> {
+        let key = Key { tonic, mode };
+        (-7..=7).contains(&key.fifths()).then_some(key)
+    }
+    // Position on the circle of fifths: positive counts sharps, negative counts flats.
+    pub fn fifths(&self) -> i8 {
+        let letter = self.tonic.letter();
+        let natural = Note::spell(letter, 0).map_or(0, |note| note.semitone());
+        let fifths = "FCGDAEB".find(letter).map_or(0, |position| position as i8 - 1) + 7 * (self.tonic.semitone() - natural);
+        match self.mode {
+            Mode::Major => fifths,
+            Mode::Minor => fifths - 3,
+        }
+    }
+    pub fn alteration(&self, letter: char) -> i8 {
+        let fifths = self.fifths();
+        let position = "FCGDAEB".find(letter).map_or(0, |position| position as i8);
+        if position < fifths {
+            1
+        } else if 6 - position < -fifths {
+            -1
+        } else {
+            0
+        }
+    }
+    // Spells a note written without an accidental.
+    pub fn apply(&self, note: Note) -> Note {
+        Note::spell(note.letter(), self.alteration(note.letter())).unwrap_or(note)
+    }
+    // The spelling this key gives a pitch class, if the pitch class is in its scale.
+    pub fn scale_note(&self, semitone: i8) -> Option<Note> {
+        "CDEFGAB"
+            .chars()
+            .map(|letter| self.apply(Note::spell(letter, 0).unwrap_or(Note::C)))
+            .find(|note| note.semitone().rem_euclid(12) == semitone.rem_euclid(12))
+    }
+}
+
+impl Default for Key {
+    fn default() -> Self {
+        Self { tonic: Note::C, mode: Mode::Major }
+    }
+}
+
+impl Instrument<'_> {
+    pub fn in_range(&self, pitch: Pitch) -> bool {
+        match self.range {
+            Some((low, high)) => (low.midi()..=high.midi()).contains(&pitch.midi()),
+            None => true,
+        }
+    }
+}
+
+impl Duration {
+    pub fn length(&self) -> Fraction {
+        let mut part = Fraction::new(1, self.value as u64);
+        let mut length = part;
+        for _ in 0..self.dots {
+            part = part * Fraction::new(1, 2);
+            length = length + part;
+        }
+        length * self.scale
+    }
+}
+
+impl Fraction {
+    pub fn new(num: u64, den: u64) -> Self {
+        let divisor = gcd(num, den).max(1);
+        Self { num: num / divisor, den: den / divisor }
+    }
+    pub fn zero() -> Self {
+        Self { num: 0, den: 1 }
+    }
+    pub fn num(&self) -> u64 {
+        self.num
+    }
+    pub fn den(&self) -> u64 {
+        self.den
+    }
+    // How far through `whole` this fraction is, for interpolating across a span.
+    pub fn ratio(self, whole: Fraction) -> f64 {
+        (self.num * whole.den) as f64 / (self.den * whole.num) as f64
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl ops::Add for Fraction {
+    type Output = Fraction;
+    fn add(self, other: Fraction) -> Fraction {
+        Fraction::new(self.num * other.den + other.num * self.den, self.den * other.den)
+    }
+}
+
+impl ops::Sub for Fraction {
+    type Output = Fraction;
+    fn sub(self, other: Fraction) -> Fraction {
+        Fraction::new(self.num * other.den - other.num * self.den, self.den * other.den)
+    }
+}
+
+impl ops::Mul for Fraction {
+    type Output = Fraction;
+    fn mul(self, other: Fraction) -> Fraction {
+        Fraction::new(self.num * other.num, self.den * other.den)
+    }
+}
+
+impl PartialEq for Fraction {
+    fn eq(&self, other: &Fraction) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Fraction {}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Fraction) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Fraction {
+    fn cmp(&self, other: &Fraction) -> Ordering {
+        (self.num * other.den).cmp(&(other.num * self.den))
+    }
+}
+
+impl fmt::Display for Fraction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.num, self.den)
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}/{}]", self.top, self.bottom)
+    }
+}
+
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.note, self.octave)
+    }
+}
+
+impl fmt::Display for Dynamic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Major => write!(f, "{} major", self.tonic),
+            Mode::Minor => write!(f, "{} minor", self.tonic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(tonic: Note, mode: Mode) -> Key {
+        Key::new(tonic, mode).unwrap()
+    }
+
+    #[test]
+    fn fifths_and_alterations() {
+        assert_eq!(key(Note::C, Mode::Major).fifths(), 0);
+        assert_eq!(key(Note::A, Mode::Minor).fifths(), 0);
+        assert_eq!(key(Note::Eb, Mode::Major).fifths(), -3);
+        assert_eq!(key(Note::Cs, Mode::Minor).fifths(), 4);
+        assert_eq!(key(Note::Cs, Mode::Major).fifths(), 7);
+        assert_eq!(key(Note::Cb, Mode::Major).fifths(), -7);
+        assert!(Key::new(Note::Gs, Mode::Major).is_none());
+        assert!(Key::new(Note::Fb, Mode::Minor).is_none());
+
+        let d = key(Note::D, Mode::Major);
+        let alterations: Vec<i8> = "CDEFGAB".chars().map(|letter| d.alteration(letter)).collect();
+        assert_eq!(alterations, [1, 0, 0, 1, 0, 0, 0]);
+        let ab = key(Note::Ab, Mode::Major);
+        let alterations: Vec<i8> = "CDEFGAB".chars().map(|letter| ab.alteration(letter)).collect();
+        assert_eq!(alterations, [0, -1, -1, 0, 0, -1, -1]);
+        assert_eq!(key(Note::Cs, Mode::Major).alteration('B'), 1);
+        assert_eq!(key(Note::Cb, Mode::Major).alteration('F'), -1);
+    }
+
+    #[test]
+    fn scale_notes_follow_the_key() {
+        let gb = key(Note::Gb, Mode::Major);
+        assert_eq!(gb.scale_note(11), Some(Note::Cb));
+        assert_eq!(gb.scale_note(6), Some(Note::Gb));
+        assert_eq!(gb.scale_note(7), None);
+        let fs = key(Note::Fs, Mode::Major);
+        assert_eq!(fs.scale_note(5), Some(Note::Es));
+        assert_eq!(fs.scale_note(-6), Some(Note::Fs));
+        assert_eq!(key(Note::C, Mode::Major).scale_note(1), None);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    errors::{CompileError::*, CompileFinalError},
+    score::*,
+    tokens::Location,
+    unroll::unroll,
+};
+
+const DEFAULT_BPM: u32 = 120;
+const FERMATA_STRETCH: u32 = 2;
+// MIDI stores a tempo as microseconds per quarter in 24 bits, which bottoms out near 3.6 bpm.
+const MAX_MICROS: u32 = 0xFF_FFFF;
+
+struct Mark {
+    position: Fraction,
+    bpm: u32,
+    staff: usize,
+    location: Location,
+}
+
+// Merges the tempo directives of every staff into one map. Staffs may repeat each other's
+// tempo changes but must not disagree about them.
+pub fn build(score: &Score) -> Result<TempoMap, Vec<CompileFinalError>> {
+    let mut marks = vec![];
+    let mut fermatas = vec![];
+    let mut errors = vec![];
+    for (index, staff) in score.staves.iter().enumerate() {
+        let measures = unroll(staff);
+        let mut starts = vec![Fraction::zero()];
+        for measure in &measures {
+            starts.push(starts[starts.len() - 1] + measure.length());
+        }
+        let mut bpm = DEFAULT_BPM;
+        for (number, measure) in measures.iter().enumerate() {
+            for (directive, location) in &measure.directives {
+                let mark = |position, bpm| Mark { position, bpm, staff: index, location: *location };
+                match *directive {
+                    Directive::Tempo(target) => {
+                        marks.push(mark(starts[number], target));
+                        bpm = target;
+                    }
+                    Directive::Ramp { target, measures: count, faster } => {
+                        if (faster && target <= bpm) || (!faster && target >= bpm) {
+                            errors.push((BadRamp(if faster { "accel" } else { "rit" }, bpm, target), *location));
+                            continue;
+                        }
+                        let start = starts[number];
+                        let last = (number + count as usize).min(measures.len());
+                        let end = starts[last];
+                        // One step per beat, following each measure's own meter.
+                        for (offset, measure) in measures[number..last].iter().enumerate() {
+                            let mut position = starts[number + offset];
+                            for beat in measure.signature.beats() {
+                                if position > start {
+                                    let step = (target as f64 - bpm as f64) * (position - start).ratio(end - start);
+                                    marks.push(mark(position, (bpm as f64 + step).round() as u32));
+                                }
+                                position = position + beat;
+                            }
+                        }
+                        marks.push(mark(end, target));
+                        bpm = target;
+                    }
+                }
+            }
+            for voice in &measure.voices {
+                let mut position = starts[number];
+                for event in &voice.events {
+                    let length = event.duration.length();
+                    if event.modifiers.iter().any(|modifier| modifier.name == "fermata") {
+                        fermatas.push((position, position + length));
+                    }
+                    position = position + length;
+                }
+            }
+        }
+    }
+    marks.sort_by_key(|mark| mark.position);
+    let mut merged: Vec<Mark> = vec![];
+    for mark in marks {
+        match merged.last_mut() {
+            Some(last) if last.position == mark.position && last.staff == mark.staff => *last = mark,
+            Some(last) if last.position == mark.position && last.bpm != mark.bpm => {
+                errors.push((TempoConflict(mark.bpm, last.bpm), mark.location));
+            }
+            Some(last) if last.position == mark.position => {}
+            _ => merged.push(mark),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let mut changes: Vec<(Fraction, u32)> = merged.iter().map(|mark| (mark.position, micros(mark.bpm))).collect();
+    fermatas.sort();
+    fermatas.dedup();
+    for (start, end) in fermatas {
+        stretch(&mut changes, start, end);
+    }
+    Ok(TempoMap { changes })
+}
+
+// Slows everything between `start` and `end`, then returns to the tempo that would have applied.
+fn stretch(changes: &mut Vec<(Fraction, u32)>, start: Fraction, end: Fraction) {
+    let at = |changes: &[(Fraction, u32)], position: Fraction| {
+        changes.iter().take_while(|(at, _)| *at <= position).last().map_or(micros(DEFAULT_BPM), |(_, micros)| *micros)
+    };
+    if !changes.iter().any(|(at, _)| *at == end) {
+        let resume = at(changes, end);
+        changes.push((end, resume));
+    }
+    if !changes.iter().any(|(at, _)| *at == start) {
+        let held = at(changes, start);
+        changes.push((start, held));
+    }
+    changes.sort_by_key(|(at, _)| *at);
+    for (at, micros) in changes.iter_mut() {
+        if *at >= start && *at < end {
+            *micros = micros.saturating_mul(FERMATA_STRETCH).min(MAX_MICROS);
+        }
+    }
+}
+
+fn micros(bpm: u32) -> u32 {
+    (60_000_000 / bpm).min(MAX_MICROS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_tempos_saturate() {
+        assert_eq!(micros(120), 500_000);
+        assert_eq!(micros(3), MAX_MICROS);
+        let mut changes = vec![(Fraction::zero(), micros(6))];
+        stretch(&mut changes, Fraction::zero(), Fraction::new(1, 4));
+        assert_eq!(changes[0].1, MAX_MICROS);
+        assert_eq!(changes[1].1, micros(6));
+    }
+}
@@ -12,36 +12,177 @@ pub enum Separator {
 impl std::fmt::Display for Separator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Separator::LParan => write!(f, "{}", "("),
-            Separator::RParan => write!(f, "{}", ")"),
-            Separator::Semicolon => write!(f, "{}", ";"),
-            Separator::Comma => write!(f, "{}", ","),
-            Separator::LCurly => write!(f, "{}", "{"),
-            Separator::RCurly => write!(f, "{}", "}"),
+            Separator::LParan => write!(f, "("),
+            Separator::RParan => write!(f, ")"),
+            Separator::Semicolon => write!(f, ";"),
+            Separator::Comma => write!(f, ","),
+            Separator::LCurly => write!(f, "{{"),
+            Separator::RCurly => write!(f, "}}"),
         }
     }
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Note {
+    Cbb,
+    Cb,
     C,
     Cs,
+    Css,
+    Dbb,
     Db,
     D,
     Ds,
+    Dss,
+    Ebb,
     Eb,
     E,
+    Es,
+    Ess,
+    Fbb,
+    Fb,
     F,
     Fs,
+    Fss,
+    Gbb,
     Gb,
     G,
     Gs,
+    Gss,
+    Abb,
     Ab,
     A,
     As,
+    Ass,
+    Bbb,
     Bb,
     B,
+    Bs,
+    Bss,
 }
-#[derive(Debug, Clone, Copy)]
+
+const SHARPS: [Note; 12] = [Note::C, Note::Cs, Note::D, Note::Ds, Note::E, Note::F, Note::Fs, Note::G, Note::Gs, Note::A, Note::As, Note::B];
+const FLATS: [Note; 12] = [Note::C, Note::Db, Note::D, Note::Eb, Note::E, Note::F, Note::Gb, Note::G, Note::Ab, Note::A, Note::Bb, Note::B];
+
+impl Note {
+    pub const DEFAULT_OCTAVE: u8 = 4;
+
+    // Cb is a semitone below C and B# a semitone above B, so this runs from -2 to 13.
+    pub fn semitone(&self) -> i8 {
+        let natural = match self.letter() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            _ => 11,
+        };
+        natural + self.accidental()
+    }
+    pub fn accidental(&self) -> i8 {
+        use Note::*;
+        match self {
+            Cbb | Dbb | Ebb | Fbb | Gbb | Abb | Bbb => -2,
+            Cb | Db | Eb | Fb | Gb | Ab | Bb => -1,
+            C | D | E | F | G | A | B => 0,
+            Cs | Ds | Es | Fs | Gs | As | Bs => 1,
+            Css | Dss | Ess | Fss | Gss | Ass | Bss => 2,
+        }
+    }
+    pub fn spell(letter: char, accidental: i8) -> Option<Note> {
+        let note = match (letter, accidental) {
+            ('C', -2) => Note::Cbb,
+            ('C', -1) => Note::Cb,
+            ('C', 0) => Note::C,
+            ('C', 1) => Note::Cs,
+            ('C', 2) => Note::Css,
+            ('D', -2) => Note::Dbb,
+            ('D', -1) => Note::Db,
+            ('D', 0) => Note::D,
+            ('D', 1) => Note::Ds,
+            ('D', 2) => Note::Dss,
+            ('E', -2) => Note::Ebb,
+            ('E', -1) => Note::Eb,
+            ('E', 0) => Note::E,
+            ('E', 1) => Note::Es,
+            ('E', 2) => Note::Ess,
+            ('F', -2) => Note::Fbb,
+            ('F', -1) => Note::Fb,
+            ('F', 0) => Note::F,
+            ('F', 1) => Note::Fs,
+            ('F', 2) => Note::Fss,
+            ('G', -2) => Note::Gbb,
+            ('G', -1) => Note::Gb,
+            ('G', 0) => Note::G,
+            ('G', 1) => Note::Gs,
+            ('G', 2) => Note::Gss,
+            ('A', -2) => Note::Abb,
+            ('A', -1) => Note::Ab,
+            ('A', 0) => Note::A,
+            ('A', 1) => Note::As,
+            ('A', 2) => Note::Ass,
+            ('B', -2) => Note::Bbb,
+            ('B', -1) => Note::Bb,
+            ('B', 0) => Note::B,
+            ('B', 1) => Note::Bs,
+            ('B', 2) => Note::Bss,
+            _ => return None,
+        };
+        Some(note)
+    }
+    pub fn from_semitone(semitone: i8, flats: bool) -> Note {
+        let names = if flats { FLATS } else { SHARPS };
+        names[semitone.rem_euclid(12) as usize]
+    }
+    pub fn letter(&self) -> char {
+        use Note::*;
+        match self {
+            Cbb | Cb | C | Cs | Css => 'C',
+            Dbb | Db | D | Ds | Dss => 'D',
+            Ebb | Eb | E | Es | Ess => 'E',
+            Fbb | Fb | F | Fs | Fss => 'F',
+            Gbb | Gb | G | Gs | Gss => 'G',
+            Abb | Ab | A | As | Ass => 'A',
+            Bbb | Bb | B | Bs | Bss => 'B',
+        }
+    }
+    // The flag is set when the note was written with an accidental, including an explicit natural `n`.
+    pub fn parse(word: &str) -> Option<(Note, u8, bool)> {
+        let mut chars = word.chars();
+        let letter = chars.next()?;
+        let rest = chars.as_str();
+        let octave = rest.trim_start_matches(['#', 'b', 'n', '♯', '♭', '♮', '𝄪', '𝄫']);
+        let accidentals = &rest[..rest.len() - octave.len()];
+        let accidental = match accidentals {
+            "" | "n" | "♮" => 0,
+            "#" | "♯" => 1,
+            "##" | "♯♯" | "𝄪" => 2,
+            "b" | "♭" => -1,
+            "bb" | "♭♭" | "𝄫" => -2,
+            _ => return None,
+        };
+        let note = Note::spell(letter, accidental)?;
+        let octave = match octave {
+            "" => Note::DEFAULT_OCTAVE,
+            octave => octave.parse::<u8>().ok().filter(|octave| *octave <= 9)?,
+        };
+        Some((note, octave, !accidentals.is_empty()))
+    }
+}
+
+impl std::fmt::Display for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let accidental = match self.accidental() {
+            -2 => "bb",
+            -1 => "b",
+            1 => "#",
+            2 => "##",
+            _ => "",
+        };
+        write!(f, "{}{}", self.letter(), accidental)
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Keyword {
     Import,
     Meta,
@@ -51,13 +192,27 @@ pub enum Keyword {
     From,
     With,
     Is,
-    In
+    In,
+    Instrument,
+    Time,
+    Repeat,
+    Ending,
+    Voice,
 }
+// `line` and `col` are where the token starts, counting characters from 1. The span holds byte offsets.
 #[derive(Debug, Clone, Copy)]
 pub struct Location {
+    pub file: usize,
     pub line: usize,
     pub col: usize,
+    pub span: Span,
+}
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
 }
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy)]
 pub enum Token<'src> {
     Separator(Separator, Location),
@@ -65,10 +220,14 @@ pub enum Token<'src> {
     Identifier(&'src str, Location),
     Signature(u32, u32, Location),
     Number(u32, Location),
-    Note(Note, u8, Location),
+    Ratio(u32, u32, Location),
+    Note(Note, u8, bool, Location),
+    // A chord symbol marked as `chord(G7)`, spanning the whole mark.
+    Chord(&'src str, Location),
     Keyword(Keyword, Location),
     EOF(Location)
 }
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum TokenType {
     Separator,
@@ -76,11 +235,69 @@ pub enum TokenType {
     Identifier,
     Signature,
     Number,
+    Ratio,
     Note,
+    Chord,
     Keyword,
     EOF
 }
 
+impl<'src> Token<'src> {
+    pub fn location(&self) -> Location {
+        match self {
+            Token::Separator(_, loc) => *loc,
+            Token::Literal(_, loc) => *loc,
+            Token::Identifier(_, loc) => *loc,
+            Token::Signature(_, _, loc) => *loc,
+            Token::Number(_, loc) => *loc,
+            Token::Ratio(_, _, loc) => *loc,
+            Token::Note(_, _, _, loc) => *loc,
+            Token::Chord(_, loc) => *loc,
+            Token::Keyword(_, loc) => *loc,
+            Token::EOF(loc) => *loc,
+        }
+    }
+    pub fn token_type(&self) -> TokenType {
+        match self {
+            Token::Separator(_, _) => TokenType::Separator,
+            Token::Literal(_, _) => TokenType::Literal,
+            Token::Identifier(_, _) => TokenType::Identifier,
+            Token::Signature(_, _, _) => TokenType::Signature,
+            Token::Number(_, _) => TokenType::Number,
+            Token::Ratio(_, _, _) => TokenType::Ratio,
+            Token::Note(_, _, _, _) => TokenType::Note,
+            Token::Chord(_, _) => TokenType::Chord,
+            Token::Keyword(_, _) => TokenType::Keyword,
+            Token::EOF(_) => TokenType::EOF,
+        }
+    }
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Separator(_, _) => format!("`{}`", self),
+            Token::Literal(_, _) => format!("{} {}", self.token_type(), self),
+            Token::EOF(_) => self.token_type().to_string(),
+            _ => format!("{} `{}`", self.token_type(), self),
+        }
+    }
+}
+
+impl std::fmt::Display for TokenType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenType::Separator => write!(f, "separator"),
+            TokenType::Literal => write!(f, "string literal"),
+            TokenType::Identifier => write!(f, "identifier"),
+            TokenType::Signature => write!(f, "time signature"),
+            TokenType::Number => write!(f, "number"),
+            TokenType::Ratio => write!(f, "ratio"),
+            TokenType::Note => write!(f, "note"),
+            TokenType::Chord => write!(f, "chord symbol"),
+            TokenType::Keyword => write!(f, "keyword"),
+            TokenType::EOF => write!(f, "end of file"),
+        }
+    }
+}
+
 impl<'src> std::fmt::Display for Token<'src> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -89,9 +306,49 @@ impl<'src> std::fmt::Display for Token<'src> {
             Token::Identifier(ident, _) => write!(f, "{}", ident),
             Token::Signature(top, bottom, _) => write!(f, "[{}/{}]", top, bottom),
             Token::Number(num, _) => write!(f, "{}", num),
-            Token::Note(note, octave, _) => write!(f, "{:?}_{}", note, octave),
+            Token::Ratio(actual, normal, _) => write!(f, "{}:{}", actual, normal),
+            Token::Note(note, octave, true, _) if !note.to_string().contains(['#', 'b']) => write!(f, "{}n{}", note, octave),
+            Token::Note(note, octave, _, _) => write!(f, "{}{}", note, octave),
+            Token::Chord(symbol, _) => write!(f, "chord({})", symbol),
             Token::Keyword(word, _) => write!(f, "{}", format!("{:?}", word).to_lowercase()),
             Token::EOF(_) => write!(f, "EOF"),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_keeps_spelling() {
+        assert_eq!(Note::parse("Ebb2"), Some((Note::Ebb, 2, true)));
+        assert_eq!(Note::parse("Cb4"), Some((Note::Cb, 4, true)));
+        assert_eq!(Note::parse("B#3"), Some((Note::Bs, 3, true)));
+        assert_eq!(Note::parse("Fn"), Some((Note::F, Note::DEFAULT_OCTAVE, true)));
+        assert_eq!(Note::parse("G"), Some((Note::G, Note::DEFAULT_OCTAVE, false)));
+        assert_eq!(Note::Ebb.semitone(), 2);
+        assert_eq!(Note::Cb.semitone(), -1);
+        assert_eq!(Note::Bs.semitone(), 12);
+        assert_eq!(Note::Ebb.to_string(), "Ebb");
+    }
+
+    #[test]
+    fn letters_and_accidentals_round_trip() {
+        for letter in "CDEFGAB".chars() {
+            for accidental in -2..=2 {
+                let note = Note::spell(letter, accidental).unwrap();
+                assert_eq!((note.letter(), note.accidental()), (letter, accidental));
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rejects_bad_notes() {
+        assert_eq!(Note::parse("C10"), None);
+        assert_eq!(Note::parse("C-1"), None);
+        assert_eq!(Note::parse("C#b4"), None);
+        assert_eq!(Note::parse("Cbbb4"), None);
+        assert_eq!(Note::parse("H4"), None);
+        assert_eq!(Note::parse(""), None);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::score::*;
+
+// Expands a staff's repeats and jumps into the order its measures are played in. The pickup,
+// if any, is played once before everything else.
+pub fn unroll<'a, 'src>(staff: &'a Staff<'src>) -> Vec<&'a Measure<'src>> {
+    let order = order(&staff.navigation, staff.measures.len());
+    staff.pickup.iter().chain(order.into_iter().map(|index| &staff.measures[index])).collect()
+}
+
+fn order(navigation: &Navigation, len: usize) -> Vec<usize> {
+    let mut order = vec![];
+    play(navigation, 0, navigation.jump.map_or(len, |jump| jump.at), true, &mut order);
+    // After a D.C. or D.S. repeats are played once, as on their final pass.
+    if let Some(jump) = navigation.jump {
+        let from = match jump.to {
+            JumpTarget::Start => 0,
+            JumpTarget::Segno => navigation.segno.unwrap_or(0),
+        };
+        match jump.until {
+            JumpEnd::End => play(navigation, from, len, false, &mut order),
+            JumpEnd::Fine => play(navigation, from, navigation.fine.unwrap_or(len), false, &mut order),
+            JumpEnd::Coda => {
+                play(navigation, from, navigation.to_coda.unwrap_or(len), false, &mut order);
+                play(navigation, navigation.coda.unwrap_or(len), len, false, &mut order);
+            }
+        }
+    }
+    order
+}
+
+fn play(navigation: &Navigation, start: usize, end: usize, repeat: bool, order: &mut Vec<usize>) {
+    let mut index = start;
+    while index < end {
+        let Some(section) = navigation.repeats.iter().find(|section| section.start == index && section.last() > index) else {
+            order.push(index);
+            index += 1;
+            continue;
+        };
+        let passes = if repeat { 1..=section.times } else { section.times..=section.times };
+        for pass in passes {
+            order.extend((section.start..section.end).filter(|index| *index < end));
+            if let Some(ending) = section.endings.iter().find(|ending| ending.passes.contains(&pass)) {
+                order.extend((ending.start..ending.end).filter(|index| *index < end));
+            }
+        }
+        index = section.last();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repeat(start: usize, end: usize, times: u32, endings: Vec<(Vec<u32>, usize, usize)>) -> Repeat {
+        let endings = endings.into_iter().map(|(passes, start, end)| Ending { passes, start, end }).collect();
+        Repeat { start, end, times, endings }
+    }
+
+    #[test]
+    fn repeats_and_endings() {
+        let navigation = Navigation { repeats: vec![repeat(1, 3, 2, vec![])], ..Navigation::default() };
+        assert_eq!(order(&navigation, 4), [0, 1, 2, 1, 2, 3]);
+        let navigation = Navigation { repeats: vec![repeat(0, 2, 3, vec![])], ..Navigation::default() };
+        assert_eq!(order(&navigation, 2), [0, 1, 0, 1, 0, 1]);
+        // Endings follow the body of the repeat on the passes they name.
+        let navigation = Navigation { repeats: vec![repeat(0, 2, 3, vec![(vec![1, 2], 2, 3), (vec![3], 3, 4)])], ..Navigation::default() };
+        assert_eq!(order(&navigation, 5), [0, 1, 2, 0, 1, 2, 0, 1, 3, 4]);
+    }
+
+    #[test]
+    fn da_capo_al_fine_plays_repeats_once() {
+        let navigation = Navigation {
+            repeats: vec![repeat(0, 2, 2, vec![(vec![1], 2, 3), (vec![2], 3, 4)])],
+            fine: Some(4),
+            jump: Some(Jump { at: 5, to: JumpTarget::Start, until: JumpEnd::Fine }),
+            ..Navigation::default()
+        };
+        assert_eq!(order(&navigation, 5), [0, 1, 2, 0, 1, 3, 4, 0, 1, 3]);
+    }
+
+    #[test]
+    fn dal_segno_al_coda() {
+        let navigation = Navigation {
+            segno: Some(1),
+            to_coda: Some(2),
+            coda: Some(4),
+            jump: Some(Jump { at: 4, to: JumpTarget::Segno, until: JumpEnd::Coda }),
+            ..Navigation::default()
+        };
+        assert_eq!(order(&navigation, 5), [0, 1, 2, 3, 1, 4]);
+    }
+
+    #[test]
+    fn missing_marks_fall_back_to_the_ends() {
+        let navigation = Navigation { jump: Some(Jump { at: 2, to: JumpTarget::Segno, until: JumpEnd::Fine }), ..Navigation::default() };
+        assert_eq!(order(&navigation, 3), [0, 1, 0, 1, 2]);
+        assert_eq!(order(&Navigation::default(), 0), Vec::<usize>::new());
+    }
+}